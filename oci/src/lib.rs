@@ -151,6 +151,12 @@ pub struct Process {
     pub selinux_label: String,
 }
 
+impl Process {
+    pub fn load(path: &str) -> Result<Process, serialize::SerializeError> {
+        serialize::deserialize(path)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Root {
     #[serde(default)]
@@ -233,7 +239,7 @@ pub struct LinuxDeviceCgroup {
     pub access: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug)]
 pub struct LinuxMemory {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<i64>,
@@ -249,7 +255,7 @@ pub struct LinuxMemory {
     pub swappiness: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug)]
 pub struct LinuxCPU {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shares: Option<u64>,
@@ -268,7 +274,7 @@ pub struct LinuxCPU {
     pub mems: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug)]
 pub struct LinuxPids {
     #[serde(default)]
     pub limit: i64,
@@ -296,7 +302,7 @@ pub struct LinuxThrottleDevice {
     pub rate: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug)]
 pub struct LinuxBlockIO {
     #[serde(skip_serializing_if = "Option::is_none", rename = "blkioWeight")]
     pub weight: Option<u16>,
@@ -372,6 +378,18 @@ pub struct LinuxResources {
     pub hugepage_limits: Vec<LinuxHugepageLimit>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub network: Option<LinuxNetwork>,
+    // NOTE: cgroup v2 interface files that have no field above, such as
+    //       memory.high, are set here by name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub unified: HashMap<String, String>,
+}
+
+impl LinuxResources {
+    pub fn load(
+        path: &str,
+    ) -> Result<LinuxResources, serialize::SerializeError> {
+        serialize::deserialize(path)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -587,3 +605,149 @@ impl State {
         serialize::to_writer(self, &mut writer)
     }
 }
+
+// NOTE: events and stats are not part of the runtime spec, so these
+//       follow the format used by runc events.
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct CpuStats {
+    #[serde(default)]
+    pub total: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", rename = "perCpu")]
+    pub per_cpu: Vec<u64>,
+    #[serde(default)]
+    pub user: u64,
+    #[serde(default)]
+    pub kernel: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct MemoryStats {
+    #[serde(default)]
+    pub usage: u64,
+    #[serde(default, rename = "maxUsage")]
+    pub max_usage: u64,
+    #[serde(default)]
+    pub failcnt: u64,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct PidsStats {
+    #[serde(default)]
+    pub current: u64,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlkioEntry {
+    #[serde(default)]
+    pub major: u64,
+    #[serde(default)]
+    pub minor: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub op: String,
+    #[serde(default)]
+    pub value: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct BlkioStats {
+    #[serde(default, skip_serializing_if = "Vec::is_empty",
+            rename = "ioServiceBytesRecursive")]
+    pub io_service_bytes: Vec<BlkioEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty",
+            rename = "ioServicedRecursive")]
+    pub io_serviced: Vec<BlkioEntry>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct HugetlbStats {
+    #[serde(default)]
+    pub usage: u64,
+    #[serde(default, rename = "maxUsage")]
+    pub max_usage: u64,
+    #[serde(default)]
+    pub failcnt: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct Stats {
+    #[serde(default)]
+    pub cpu: CpuStats,
+    #[serde(default)]
+    pub memory: MemoryStats,
+    #[serde(default)]
+    pub pids: PidsStats,
+    #[serde(default)]
+    pub blkio: BlkioStats,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub hugetlb: HashMap<String, HugetlbStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub typ: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Stats>,
+}
+
+impl Event {
+    pub fn to_string(&self) -> Result<String, serialize::SerializeError> {
+        serialize::to_string(self)
+    }
+}
+
+// NOTE: this is not part of the spec, but matches the output of runc list
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContainerState {
+    #[serde(rename = "ociVersion")]
+    pub version: String,
+    pub id: String,
+    pub pid: i32,
+    pub status: String,
+    pub bundle: String,
+    pub created: String,
+    pub owner: String,
+}
+
+// NOTE: this is not part of the spec. Railcar keeps it in the state dir
+//       so that it can tell if the container processes are still the
+//       ones that it started.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateRecord {
+    pub pid: i32,
+    // start time of the process in clock ticks after boot
+    #[serde(rename = "startTime")]
+    pub start_time: u64,
+    #[serde(default, rename = "processPid")]
+    pub process_pid: i32,
+    #[serde(default, rename = "processStartTime")]
+    pub process_start_time: u64,
+    pub created: String,
+    pub owner: u32,
+    pub bundle: String,
+    #[serde(rename = "configDigest")]
+    pub config_digest: String,
+    #[serde(default, rename = "listenFds")]
+    pub listen_fds: i32,
+    #[serde(default, skip_serializing_if = "String::is_empty",
+            rename = "listenFdNames")]
+    pub listen_fd_names: String,
+    #[serde(default, rename = "preserveFds")]
+    pub preserve_fds: i32,
+}
+
+impl StateRecord {
+    pub fn load(path: &str) -> Result<StateRecord, serialize::SerializeError> {
+        serialize::deserialize(path)
+    }
+
+    pub fn to_string(&self) -> Result<String, serialize::SerializeError> {
+        serialize::to_string(self)
+    }
+}
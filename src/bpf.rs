@@ -0,0 +1,373 @@
+// Device filtering for cgroup v2, which replaces devices.allow and
+// devices.deny with a BPF_PROG_TYPE_CGROUP_DEVICE program.
+use errors::*;
+use libc;
+use nix::Errno;
+use nix::fcntl::{open, O_DIRECTORY, O_RDONLY};
+use nix::sys::stat::Mode;
+use nix::unistd::close;
+use oci::{LinuxDeviceCgroup, LinuxDeviceType};
+use std::mem::size_of;
+
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_PROG_ATTACH: libc::c_int = 8;
+const BPF_PROG_TYPE_CGROUP_DEVICE: u32 = 15;
+const BPF_CGROUP_DEVICE: u32 = 6;
+
+// values from struct bpf_cgroup_dev_ctx
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+const BPF_DEVCG_ACC_MKNOD: i32 = 1;
+const BPF_DEVCG_ACC_READ: i32 = 2;
+const BPF_DEVCG_ACC_WRITE: i32 = 4;
+
+// instruction opcodes
+const LDX_MEM_W: u8 = 0x61;
+const ALU_AND_K: u8 = 0x54;
+const ALU_RSH_K: u8 = 0x74;
+const ALU_MOV_X: u8 = 0xbc;
+const ALU64_MOV_K: u8 = 0xb7;
+const JMP_JEQ_K: u8 = 0x15;
+const JMP_JNE_K: u8 = 0x55;
+const JMP_JNE_X: u8 = 0x5d;
+const JMP_EXIT: u8 = 0x95;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn {
+        code: code,
+        regs: (src << 4) | (dst & 0xf),
+        off: off,
+        imm: imm,
+    }
+}
+
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+#[repr(C)]
+struct ProgAttachAttr {
+    target_fd: u32,
+    attach_bpf_fd: u32,
+    attach_type: u32,
+    attach_flags: u32,
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &T) -> ::nix::Result<libc::c_int> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T as *const libc::c_void,
+            size_of::<T>() as libc::c_uint,
+        )
+    };
+    Errno::result(res).map(|fd| fd as libc::c_int)
+}
+
+fn is_jump(code: u8) -> bool {
+    code == JMP_JEQ_K || code == JMP_JNE_K || code == JMP_JNE_X
+}
+
+fn access_bits(access: &str) -> Result<i32> {
+    // NOTE: an empty access string is treated as rwm
+    if access.is_empty() {
+        return Ok(BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ |
+            BPF_DEVCG_ACC_WRITE);
+    }
+    let mut bits = 0;
+    for c in access.chars() {
+        bits |= match c {
+            'm' => BPF_DEVCG_ACC_MKNOD,
+            'r' => BPF_DEVCG_ACC_READ,
+            'w' => BPF_DEVCG_ACC_WRITE,
+            _ => {
+                let msg = format!("invalid device access {}", access);
+                bail!(ErrorKind::InvalidSpec(msg));
+            }
+        }
+    }
+    Ok(bits)
+}
+
+fn rule_block(d: &LinuxDeviceCgroup) -> Result<(Vec<Insn>, bool)> {
+    // registers hold: r2 = type, r3 = access, r4 = major, r5 = minor
+    // each check jumps to the end of the block if the rule doesn't match,
+    // so we collect the checks first and fix up the offsets afterwards
+    let mut checks = Vec::new();
+    match d.typ {
+        LinuxDeviceType::a => {}
+        LinuxDeviceType::b => {
+            checks.push(insn(JMP_JNE_K, 2, 0, 0, BPF_DEVCG_DEV_BLOCK));
+        }
+        LinuxDeviceType::c => {
+            checks.push(insn(JMP_JNE_K, 2, 0, 0, BPF_DEVCG_DEV_CHAR));
+        }
+        _ => {
+            let msg = "invalid cgroup device type".to_string();
+            bail!(ErrorKind::InvalidSpec(msg));
+        }
+    }
+    let all = BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE;
+    let access = access_bits(&d.access)?;
+    if access != all {
+        checks.push(insn(ALU_MOV_X, 1, 3, 0, 0));
+        checks.push(insn(ALU_AND_K, 1, 0, 0, access));
+        if d.allow {
+            // requested access must be a subset of the rule's access
+            checks.push(insn(JMP_JNE_X, 1, 3, 0, 0));
+        } else {
+            // NOTE: like v1, a deny rule matches if any of the requested
+            //       access is denied, so rw is denied by a w rule
+            checks.push(insn(JMP_JEQ_K, 1, 0, 0, 0));
+        }
+    }
+    if let Some(major) = d.major {
+        if major >= 0 {
+            checks.push(insn(JMP_JNE_K, 4, 0, 0, major as i32));
+        }
+    }
+    if let Some(minor) = d.minor {
+        if minor >= 0 {
+            checks.push(insn(JMP_JNE_K, 5, 0, 0, minor as i32));
+        }
+    }
+    let catch_all = checks.is_empty();
+    let n = checks.len() + 2;
+    let mut block = Vec::with_capacity(n);
+    for (i, mut c) in checks.into_iter().enumerate() {
+        if is_jump(c.code) {
+            c.off = (n - i - 1) as i16;
+        }
+        block.push(c);
+    }
+    block.push(insn(ALU64_MOV_K, 0, 0, 0, d.allow as i32));
+    block.push(insn(JMP_EXIT, 0, 0, 0, 0));
+    Ok((block, catch_all))
+}
+
+fn build_program(rules: &[&LinuxDeviceCgroup]) -> Result<Vec<Insn>> {
+    let mut prog = vec![
+        // load type and access from access_type
+        insn(LDX_MEM_W, 2, 1, 0, 0),
+        insn(ALU_AND_K, 2, 0, 0, 0xffff),
+        insn(LDX_MEM_W, 3, 1, 0, 0),
+        insn(ALU_RSH_K, 3, 0, 0, 16),
+        // load major and minor
+        insn(LDX_MEM_W, 4, 1, 4, 0),
+        insn(LDX_MEM_W, 5, 1, 8, 0),
+    ];
+    // NOTE: the v1 device cgroup applies rules in order, so the last
+    //       matching rule wins. We check them in reverse and stop at the
+    //       first match. Anything before a catch-all rule is unreachable.
+    let mut catch_all = false;
+    for d in rules.iter().rev() {
+        let (block, all) = rule_block(d)?;
+        prog.extend(block);
+        if all {
+            catch_all = true;
+            break;
+        }
+    }
+    if !catch_all {
+        // like v1, devices are allowed unless a rule denies them
+        prog.push(insn(ALU64_MOV_K, 0, 0, 0, 1));
+        prog.push(insn(JMP_EXIT, 0, 0, 0, 0));
+    }
+    Ok(prog)
+}
+
+pub fn apply_device_rules(
+    rules: &[&LinuxDeviceCgroup],
+    dir: &str,
+) -> Result<()> {
+    let prog = build_program(rules)?;
+    let license = b"Apache\0";
+    let load = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_CGROUP_DEVICE,
+        insn_cnt: prog.len() as u32,
+        insns: prog.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+        prog_name: [0; 16],
+        prog_ifindex: 0,
+        expected_attach_type: 0,
+    };
+    debug!("loading device filter with {} instructions", prog.len());
+    let prog_fd = bpf(BPF_PROG_LOAD, &load)
+        .chain_err(|| "failed to load device filter")?;
+    defer!({ let _ = close(prog_fd); });
+    let cgroup_fd = open(dir, O_DIRECTORY | O_RDONLY, Mode::empty())?;
+    defer!({ let _ = close(cgroup_fd); });
+    // NOTE: attaching without flags replaces any existing program
+    let attach = ProgAttachAttr {
+        target_fd: cgroup_fd as u32,
+        attach_bpf_fd: prog_fd as u32,
+        attach_type: BPF_CGROUP_DEVICE,
+        attach_flags: 0,
+    };
+    bpf(BPF_PROG_ATTACH, &attach).chain_err(
+        || format!("failed to attach device filter to {}", dir),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs prog against a device access the way the kernel would, but
+    // only understands the instructions build_program emits
+    fn run(prog: &[Insn], typ: i32, access: i32, major: i32, minor: i32)
+        -> i32 {
+        let ctx = [(access << 16) | typ, major, minor];
+        let mut r = [0i64; 11];
+        let mut pc = 0;
+        loop {
+            let i = prog[pc];
+            let (dst, src) = ((i.regs & 0xf) as usize, (i.regs >> 4) as usize);
+            pc += 1;
+            match i.code {
+                LDX_MEM_W => r[dst] = ctx[i.off as usize / 4] as i64,
+                ALU_AND_K => r[dst] &= i.imm as i64,
+                ALU_RSH_K => r[dst] = (r[dst] as u32 >> i.imm) as i64,
+                ALU_MOV_X => r[dst] = r[src],
+                ALU64_MOV_K => r[dst] = i.imm as i64,
+                JMP_JNE_K => {
+                    if r[dst] != i.imm as i64 {
+                        pc += i.off as usize;
+                    }
+                }
+                JMP_JEQ_K => {
+                    if r[dst] == i.imm as i64 {
+                        pc += i.off as usize;
+                    }
+                }
+                JMP_JNE_X => {
+                    if r[dst] != r[src] {
+                        pc += i.off as usize;
+                    }
+                }
+                JMP_EXIT => return r[0] as i32,
+                c => panic!("unexpected opcode {:#x}", c),
+            }
+        }
+    }
+
+    fn rule(allow: bool, typ: LinuxDeviceType, major: Option<i64>,
+            minor: Option<i64>, access: &str) -> LinuxDeviceCgroup {
+        LinuxDeviceCgroup {
+            allow: allow,
+            typ: typ,
+            major: major,
+            minor: minor,
+            access: access.to_string(),
+        }
+    }
+
+    const R: i32 = BPF_DEVCG_ACC_READ;
+    const W: i32 = BPF_DEVCG_ACC_WRITE;
+    const M: i32 = BPF_DEVCG_ACC_MKNOD;
+    const C: i32 = BPF_DEVCG_DEV_CHAR;
+    const B: i32 = BPF_DEVCG_DEV_BLOCK;
+
+    #[test]
+    fn access() {
+        assert_eq!(access_bits("").unwrap(), R | W | M);
+        assert_eq!(access_bits("rwm").unwrap(), R | W | M);
+        assert_eq!(access_bits("r").unwrap(), R);
+        assert_eq!(access_bits("wm").unwrap(), W | M);
+        assert!(access_bits("rx").is_err());
+    }
+
+    #[test]
+    fn no_rules() {
+        let prog = build_program(&[]).unwrap();
+        assert_eq!(run(&prog, C, R, 1, 3), 1);
+    }
+
+    #[test]
+    fn rule_offsets() {
+        let d = rule(true, LinuxDeviceType::c, Some(1), Some(3), "r");
+        let (block, all) = rule_block(&d).unwrap();
+        assert!(!all);
+        // type, 3 for access, major, minor, then mov and exit
+        assert_eq!(block.len(), 8);
+        let offs = |block: &[Insn]| -> Vec<i16> {
+            block.iter().filter(|i| is_jump(i.code)).map(|i| i.off).collect()
+        };
+        assert_eq!(offs(&block), vec![7, 4, 3, 2]);
+        let d = rule(false, LinuxDeviceType::b, Some(8), None, "w");
+        let (block, all) = rule_block(&d).unwrap();
+        assert!(!all);
+        assert_eq!(block.len(), 7);
+        assert_eq!(block[3].code, JMP_JEQ_K);
+        assert_eq!(offs(&block), vec![6, 3, 2]);
+        let (block, all) = rule_block(&rule(false, LinuxDeviceType::a,
+            None, None, "rwm")).unwrap();
+        assert!(all);
+        assert_eq!(block.len(), 2);
+        assert!(rule_block(&rule(true, LinuxDeviceType::u, None, None, ""))
+            .is_err());
+    }
+
+    #[test]
+    fn default_deny() {
+        let deny = rule(false, LinuxDeviceType::a, None, None, "rwm");
+        let null = rule(true, LinuxDeviceType::c, Some(1), Some(3), "rwm");
+        let tty = rule(true, LinuxDeviceType::c, Some(136), None, "rw");
+        let prog = build_program(&[&deny, &null, &tty]).unwrap();
+        assert_eq!(run(&prog, C, R | W, 1, 3), 1);
+        assert_eq!(run(&prog, C, M, 1, 3), 1);
+        assert_eq!(run(&prog, B, R, 1, 3), 0);
+        assert_eq!(run(&prog, C, R, 1, 5), 0);
+        assert_eq!(run(&prog, C, W, 136, 4), 1);
+        assert_eq!(run(&prog, C, R | W, 136, 0), 1);
+        assert_eq!(run(&prog, C, M, 136, 4), 0);
+        assert_eq!(run(&prog, C, R | M, 136, 4), 0);
+        assert_eq!(run(&prog, B, R, 8, 0), 0);
+    }
+
+    #[test]
+    fn last_match_wins() {
+        let allow = rule(true, LinuxDeviceType::a, None, None, "");
+        let deny = rule(false, LinuxDeviceType::b, Some(8), None, "w");
+        let prog = build_program(&[&allow, &deny]).unwrap();
+        assert_eq!(run(&prog, B, W, 8, 1), 0);
+        assert_eq!(run(&prog, B, R | W, 8, 1), 0);
+        assert_eq!(run(&prog, B, W | M, 8, 1), 0);
+        assert_eq!(run(&prog, B, R, 8, 1), 1);
+        assert_eq!(run(&prog, B, R | M, 8, 1), 1);
+        assert_eq!(run(&prog, B, W, 9, 1), 1);
+        assert_eq!(run(&prog, C, W, 8, 1), 1);
+        // a later catch-all hides everything before it
+        let prog = build_program(&[&deny, &allow]).unwrap();
+        assert_eq!(run(&prog, B, W, 8, 1), 1);
+        assert_eq!(prog.len(), 8);
+    }
+}
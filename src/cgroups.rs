@@ -1,18 +1,42 @@
+use bpf;
 use errors::*;
 use lazy_static::initialize;
+use libc;
+use nix::fcntl::{open, O_RDONLY, O_CLOEXEC};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, read};
+use nix_ext::{eventfd, inotify_init1, inotify_add_watch};
 use num_traits::identities::Zero;
 use oci::{LinuxResources, LinuxThrottleDevice, LinuxDeviceCgroup};
-use oci::LinuxDeviceType;
+use oci::{LinuxDeviceType, Stats, BlkioEntry, HugetlbStats};
 use std::collections::HashMap;
+use std::ffi::CString;
 use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::RawFd;
+use std::path::Path;
 use std::string::ToString;
-use std::fs::{File, create_dir_all, remove_dir};
+use std::fs::{File, create_dir_all, read_dir, remove_dir};
+use std::thread::sleep;
+use std::time::Duration;
+
+// milliseconds to wait for a freezer state transition
+const FREEZE_TIMEOUT: i32 = 5000;
+const FREEZE_INTERVAL: i32 = 10;
 
 pub fn init() {
     // initialize lazy_static maps
     initialize(&PATHS);
     initialize(&MOUNTS);
+    initialize(&UNIFIED);
     initialize(&APPLIES);
+    initialize(&UNIFIED_APPLIES);
+}
+
+// NOTE: the unified hierarchy is only used if there are no v1 hierarchies
+//       mounted. On hybrid hosts the cgroup2 mount has no controllers, so
+//       we stick with v1.
+pub fn is_unified() -> bool {
+    MOUNTS.is_empty() && UNIFIED.is_some()
 }
 
 pub fn apply(
@@ -20,6 +44,12 @@ pub fn apply(
     pid: &str,
     cgroups_path: &str,
 ) -> Result<()> {
+    if is_unified() {
+        return apply_unified(resources, pid, cgroups_path);
+    }
+    if let Some(ref r) = *resources {
+        check_legacy(r)?;
+    }
     for key in MOUNTS.keys() {
         let dir = if let Some(s) = path(key, cgroups_path) {
             s
@@ -46,8 +76,69 @@ pub fn apply(
     Ok(())
 }
 
+// NOTE: only the limits that update can change are reapplied. Applying
+//       devices again would briefly deny every device to a running
+//       container.
+const UPDATES: &[&str] = &["cpuset", "cpu", "memory", "blkio", "pids"];
+const UNIFIED_UPDATES: &[&str] = &["cpuset", "cpu", "memory", "io", "pids"];
+
+// applies resources to an existing cgroup without moving any processes
+pub fn update(r: &LinuxResources, cgroups_path: &str) -> Result<()> {
+    if let Some(dir) = unified_dir(cgroups_path) {
+        let controllers = read_file(&dir, "cgroup.controllers")?;
+        for c in controllers.split_whitespace() {
+            if !UNIFIED_UPDATES.contains(&c) {
+                continue;
+            }
+            if let Some(cgroup_apply) = UNIFIED_APPLIES.get(c) {
+                cgroup_apply(r, &dir)?;
+            }
+        }
+        return write_unified(r, &dir);
+    }
+    check_legacy(r)?;
+    for key in MOUNTS.keys() {
+        let dir = if let Some(s) = path(key, cgroups_path) {
+            s
+        } else {
+            continue;
+        };
+        for k in key.split(',') {
+            if !UPDATES.contains(&k) {
+                continue;
+            }
+            if let Some(cgroup_apply) = APPLIES.get(k) {
+                cgroup_apply(r, &dir)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn join(pid: &str, cgroups_path: &str) -> Result<()> {
+    if let Some(dir) = unified_dir(cgroups_path) {
+        return write_file(&dir, "cgroup.procs", pid);
+    }
+    for key in MOUNTS.keys() {
+        let dir = if let Some(s) = path(key, cgroups_path) {
+            s
+        } else {
+            continue;
+        };
+        // only enter cgroups that apply would have entered
+        if key.split(',').any(|k| APPLIES.contains_key(k)) {
+            write_file(&dir, "cgroup.procs", pid)?;
+        }
+    }
+    Ok(())
+}
 
 pub fn remove(cgroups_path: &str) -> Result<()> {
+    if let Some(dir) = unified_dir(cgroups_path) {
+        debug!{"removing cgroup dir {}", &dir};
+        let chain = || format!("remove cgroup dir {} failed", &dir);
+        return ignore_missing(remove_dir(&dir)).chain_err(chain);
+    }
     for key in MOUNTS.keys() {
         let dir = if let Some(s) = path(key, cgroups_path) {
             s
@@ -57,11 +148,23 @@ pub fn remove(cgroups_path: &str) -> Result<()> {
         debug!{"removing cgroup dir {}", &dir};
         // remove cgroup dir
         let chain = || format!("remove cgroup dir {} failed", &dir);
-        remove_dir(&dir).chain_err(chain)?;
+        ignore_missing(remove_dir(&dir)).chain_err(chain)?;
     }
     Ok(())
 }
 
+// NOTE: systemd removes the cgroup for a scope when the scope exits, so
+//       it may already be gone
+#[inline]
+fn ignore_missing(r: ::std::io::Result<()>) -> ::std::io::Result<()> {
+    match r {
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => {
+            Ok(())
+        }
+        r => r,
+    }
+}
+
 #[inline]
 fn wrnz<T: ToString + Zero>(
     dir: &str,
@@ -130,32 +233,146 @@ pub fn path(key: &str, cgroups_path: &str) -> Option<String> {
     }
 }
 
-pub fn get_procs(key: &str, cgroups_path: &str) -> Vec<i32> {
-    let mut result = Vec::new();
-    if let Some(dir) = path(key, cgroups_path) {
-        let path = format!{"{}/cgroup.procs", dir};
-        let f = match File::open(path) {
-            Ok(f) => f,
-            Err(e) => {
-                warn!{"could not cgroup.procs: {}", e};
-                return result;
+// finds the path for a controller that may be mounted with others
+fn controller_path(controller: &str, cgroups_path: &str) -> Option<String> {
+    for key in MOUNTS.keys() {
+        if key.split(',').any(|k| k == controller) {
+            return path(key, cgroups_path);
+        }
+    }
+    None
+}
+
+fn procs_dir(key: &str, cgroups_path: &str) -> Option<String> {
+    if is_unified() {
+        unified_path(cgroups_path)
+    } else {
+        path(key, cgroups_path)
+    }
+}
+
+pub fn get_all_procs(key: &str, cgroups_path: &str) -> Vec<i32> {
+    if procs_dir(key, cgroups_path).is_none() {
+        return Vec::new();
+    }
+    match read_all_procs(key, cgroups_path) {
+        Ok(procs) => procs,
+        Err(e) => {
+            warn!{"could not read cgroup procs: {}", e};
+            Vec::new()
+        }
+    }
+}
+
+// returns the processes in the cgroup and its descendants, failing if the
+// cgroup itself can't be read
+pub fn read_all_procs(key: &str, cgroups_path: &str) -> Result<Vec<i32>> {
+    let dir = match procs_dir(key, cgroups_path) {
+        Some(dir) => dir,
+        None => bail!(format!{"no {} cgroup for {}", key, cgroups_path}),
+    };
+    let mut result: Vec<i32> = read_file(&dir, "cgroup.procs")?
+        .lines()
+        .filter_map(|l| l.parse().ok())
+        .collect();
+    for entry in read_dir(&dir)? {
+        if let Ok(entry) = entry {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                let child = format!(
+                    "{}/{}",
+                    cgroups_path.trim_right_matches('/'),
+                    entry.file_name().to_string_lossy()
+                );
+                result.extend(get_all_procs(key, &child));
             }
-        };
-        for line in BufReader::new(f).lines() {
-            let l = match line {
-                Ok(l) => l,
-                Err(e) => {
-                    warn!("failed to read cgroup info: {}", e);
-                    return result;
-                }
-            };
-            if let Ok(pid) = l.parse::<i32>() {
-                result.push(pid);
+        }
+    }
+    Ok(result)
+}
+
+pub fn freeze(cgroups_path: &str) -> Result<()> {
+    debug!{"freezing cgroup {}", cgroups_path};
+    set_frozen(cgroups_path, true)
+}
+
+pub fn thaw(cgroups_path: &str) -> Result<()> {
+    debug!{"thawing cgroup {}", cgroups_path};
+    set_frozen(cgroups_path, false)
+}
+
+pub fn is_frozen(cgroups_path: &str) -> bool {
+    if let Some(dir) = path("freezer", cgroups_path) {
+        match read_file(&dir, "freezer.state") {
+            Ok(state) => state.trim() == "FROZEN",
+            Err(_) => false,
+        }
+    } else if let Some(dir) = unified_path(cgroups_path) {
+        match read_file(&dir, "cgroup.events") {
+            Ok(events) => events.lines().any(|l| l == "frozen 1"),
+            Err(_) => false,
+        }
+    } else {
+        false
+    }
+}
+
+fn set_frozen(cgroups_path: &str, frozen: bool) -> Result<()> {
+    // NOTE: the freezer may take a while to stop all tasks, so we keep
+    //       writing the state until the kernel reports the transition
+    //       has completed.
+    let mut waited = 0;
+    if let Some(dir) = path("freezer", cgroups_path) {
+        let state = if frozen { "FROZEN" } else { "THAWED" };
+        loop {
+            write_file(&dir, "freezer.state", state)?;
+            if read_file(&dir, "freezer.state")?.trim() == state {
+                return Ok(());
             }
+            waited = wait_for_freezer(waited)?;
         }
+    } else if let Some(dir) = unified_path(cgroups_path) {
+        let state = if frozen { "1" } else { "0" };
+        write_file(&dir, "cgroup.freeze", state)?;
+        let expected = format!{"frozen {}", state};
+        loop {
+            let events = read_file(&dir, "cgroup.events")?;
+            if events.lines().any(|l| l == expected) {
+                return Ok(());
+            }
+            waited = wait_for_freezer(waited)?;
+        }
+    } else {
+        bail!("freezer cgroup is not mounted");
+    }
+}
 
+#[inline]
+fn wait_for_freezer(waited: i32) -> Result<i32> {
+    if waited >= FREEZE_TIMEOUT {
+        bail!(ErrorKind::Timeout(FREEZE_TIMEOUT));
+    }
+    sleep(Duration::from_millis(FREEZE_INTERVAL as u64));
+    Ok(waited + FREEZE_INTERVAL)
+}
+
+pub fn unified_path(cgroups_path: &str) -> Option<String> {
+    // NOTE: unlike v1 hierarchies, the container cgroup is created
+    //       relative to the root of the unified hierarchy, since our own
+    //       cgroup contains processes and can't delegate controllers.
+    if let Some(ref mount) = *UNIFIED {
+        Some(format!{"{}{}", mount, cgroups_path})
+    } else {
+        None
+    }
+}
+
+#[inline]
+fn unified_dir(cgroups_path: &str) -> Option<String> {
+    if is_unified() {
+        unified_path(cgroups_path)
+    } else {
+        None
     }
-    result
 }
 
 lazy_static! {
@@ -242,6 +459,41 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    // mount point of the cgroup2 (unified) hierarchy, if any
+    pub static ref UNIFIED: Option<String> = {
+        let f = match File::open("/proc/self/mountinfo") {
+            Ok(f) => f,
+            Err(e) => {
+                warn!{"could not load mount info: {}", e};
+                return None;
+            }
+        };
+        for line in BufReader::new(f).lines() {
+            let l = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    warn!("failed to read mount info: {}", e);
+                    return None;
+                }
+            };
+            if let Some(sep) = l.find(" - ") {
+                let post: Vec<&str> = l[sep+3..].split(' ').collect();
+                if post[0] != "cgroup2" {
+                    continue
+                }
+                let pre: Vec<&str> = l[..sep].split(' ').collect();
+                if pre.len() < 6 {
+                    warn!("mountinfo data is corrupted");
+                    continue
+                }
+                return Some(pre[4].to_string());
+            }
+        }
+        None
+    };
+}
+
 type Apply = fn(&LinuxResources, &str) -> Result<()>;
 
 lazy_static! {
@@ -453,20 +705,542 @@ fn write_device(d: &LinuxDeviceCgroup, dir: &str) -> Result<()> {
     write_file(dir, key, &val)
 }
 
+fn default_device_rules() -> Vec<LinuxDeviceCgroup> {
+    super::DEFAULT_DEVICES
+        .iter()
+        .map(|d| {
+            LinuxDeviceCgroup {
+                allow: true,
+                typ: d.typ,
+                major: Some(d.major as i64),
+                minor: Some(d.minor as i64),
+                access: "rwm".to_string(),
+            }
+        })
+        .collect()
+}
+
 fn devices_apply(r: &LinuxResources, dir: &str) -> Result<()> {
     for d in &r.devices {
         write_device(d, dir)?;
     }
-    for d in super::DEFAULT_DEVICES.iter() {
-        let ld = LinuxDeviceCgroup {
-            allow: true,
-            typ: d.typ,
-            major: Some(d.major as i64),
-            minor: Some(d.minor as i64),
-            access: "rwm".to_string(),
-        };
+    for ld in &default_device_rules() {
+        write_device(ld, dir)?;
+    }
+    Ok(())
+}
+
+lazy_static! {
+    static ref UNIFIED_APPLIES: HashMap<&'static str, Apply> = {
+        let mut m: HashMap<&'static str, Apply> = HashMap::new();
+        m.insert("cpuset", cpuset2_apply);
+        m.insert("cpu", cpu2_apply);
+        m.insert("memory", memory2_apply);
+        m.insert("io", io2_apply);
+        m.insert("pids", pids_apply); // pids.max is the same in v2
+        m.insert("hugetlb", hugetlb2_apply);
+        m
+    };
+}
 
-        write_device(&ld, dir)?;
+fn apply_unified(
+    resources: &Option<LinuxResources>,
+    pid: &str,
+    cgroups_path: &str,
+) -> Result<()> {
+    let dir = unified_path(cgroups_path).unwrap();
+    debug!{"creating cgroup dir {}", &dir};
+    let chain = || format!("create cgroup dir {} failed", &dir);
+    create_dir_all(&dir).chain_err(chain)?;
+    enable_controllers(cgroups_path)?;
+    if let Some(ref r) = *resources {
+        set_unified(r, &dir)?;
+    } else {
+        // apply with empty resources
+        set_unified(&LinuxResources::default(), &dir)?;
+    }
+    write_file(&dir, "cgroup.procs", pid)
+}
+
+fn set_unified(r: &LinuxResources, dir: &str) -> Result<()> {
+    let controllers = read_file(dir, "cgroup.controllers")?;
+    for c in controllers.split_whitespace() {
+        if let Some(cgroup_apply) = UNIFIED_APPLIES.get(c) {
+            cgroup_apply(r, dir)?;
+        }
+    }
+    // devices is not a controller in v2, so it is always applied
+    devices2_apply(r, dir)?;
+    unsupported_unified(r);
+    write_unified(r, dir)
+}
+
+// NOTE: these are written last so they override the values above
+fn write_unified(r: &LinuxResources, dir: &str) -> Result<()> {
+    for (key, val) in &r.unified {
+        if key.contains('/') || !key.contains('.') {
+            let msg = format!{"invalid unified resource {}", key};
+            bail!(ErrorKind::InvalidSpec(msg));
+        }
+        write_file(dir, key, val)?;
+    }
+    Ok(())
+}
+
+fn check_legacy(r: &LinuxResources) -> Result<()> {
+    if !r.unified.is_empty() {
+        let msg = "unified resources require cgroup v2".to_string();
+        bail!(ErrorKind::InvalidSpec(msg));
+    }
+    Ok(())
+}
+
+fn enable_controllers(cgroups_path: &str) -> Result<()> {
+    // controllers must be enabled in every ancestor of the container
+    // cgroup for its interface files to exist
+    let mut dir = UNIFIED.as_ref().unwrap().to_string();
+    let components: Vec<&str> =
+        cgroups_path.split('/').filter(|c| !c.is_empty()).collect();
+    for c in &components {
+        let controllers = read_file(&dir, "cgroup.controllers")?;
+        for controller in controllers.split_whitespace() {
+            let val = format!{"+{}", controller};
+            if let Err(e) = write_file(&dir, "cgroup.subtree_control", &val) {
+                warn!{"could not enable {} in {}: {}", controller, &dir, e};
+            }
+        }
+        dir = format!{"{}/{}", dir, c};
+    }
+    Ok(())
+}
+
+fn unsupported_unified(r: &LinuxResources) {
+    if r.network.is_some() {
+        warn!{"network cgroup settings are not supported on cgroup v2"};
+    }
+    if r.disable_oom_killer {
+        warn!{"disabling the oom killer is not supported on cgroup v2"};
+    }
+}
+
+// -1 means unlimited in the spec
+#[inline]
+fn max(value: i64) -> String {
+    if value < 0 {
+        "max".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn cpuset2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    // NOTE: unlike v1, empty cpuset files are inherited from the parent
+    if let Some(cpu) = r.cpu.as_ref() {
+        if !cpu.cpus.is_empty() {
+            write_file(dir, "cpuset.cpus", &cpu.cpus)?;
+        }
+        if !cpu.mems.is_empty() {
+            write_file(dir, "cpuset.mems", &cpu.mems)?;
+        }
     }
     Ok(())
 }
+
+#[inline]
+pub fn cpu_weight(shares: u64) -> u64 {
+    // convert from [2-262144] to [1-10000]
+    let shares = if shares < 2 { 2 } else { shares };
+    1 + ((shares - 2) * 9999) / 262142
+}
+
+fn cpu2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    if let Some(cpu) = r.cpu.as_ref() {
+        if let Some(shares) = cpu.shares {
+            if shares != 0 {
+                let weight = cpu_weight(shares);
+                write_file(dir, "cpu.weight", &weight.to_string())?;
+            }
+        }
+        let quota = cpu.quota.unwrap_or(0);
+        let period = cpu.period.unwrap_or(0);
+        if quota != 0 || period != 0 {
+            let quota = if quota > 0 {
+                quota.to_string()
+            } else {
+                "max".to_string()
+            };
+            let period = if period != 0 { period } else { 100000 };
+            let val = format!{"{} {}", quota, period};
+            write_file(dir, "cpu.max", &val)?;
+        }
+        if cpu.realtime_runtime.unwrap_or(0) != 0 ||
+            cpu.realtime_period.unwrap_or(0) != 0
+        {
+            warn!{"realtime cpu settings are not supported on cgroup v2"};
+        }
+    };
+    Ok(())
+}
+
+fn memory2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    if let Some(memory) = r.memory.as_ref() {
+        // NOTE: these values are nullable in the spec, but runc treats
+        //       null as a zero value
+        let limit = memory.limit.unwrap_or(0);
+        if limit != 0 {
+            write_file(dir, "memory.max", &max(limit))?;
+        }
+        // NOTE: runc maps the soft limit to memory.low
+        let reservation = memory.reservation.unwrap_or(0);
+        if reservation != 0 {
+            write_file(dir, "memory.low", &max(reservation))?;
+        }
+        // NOTE: v1 swap includes memory, but memory.swap.max doesn't
+        let swap = memory.swap.unwrap_or(0);
+        if swap < 0 {
+            write_file(dir, "memory.swap.max", "max")?;
+        } else if swap > 0 {
+            if limit <= 0 || swap < limit {
+                let msg = "swap must be at least the memory limit".to_string();
+                bail!(ErrorKind::InvalidSpec(msg));
+            }
+            let val = (swap - limit).to_string();
+            write_file(dir, "memory.swap.max", &val)?;
+        }
+        if memory.kernel.unwrap_or(0) != 0 ||
+            memory.kernel_tcp.unwrap_or(0) != 0
+        {
+            warn!{"kernel memory limits are not supported on cgroup v2"};
+        }
+        if memory.swappiness.is_some() {
+            warn!{"memory swappiness is not supported on cgroup v2"};
+        }
+    };
+    Ok(())
+}
+
+#[inline]
+pub fn io_weight(weight: u16) -> u16 {
+    // convert from [10-1000] to [1-10000]
+    let weight = if weight < 10 { 10 } else { weight as u32 };
+    (1 + (weight - 10) * 9999 / 990) as u16
+}
+
+fn io2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    if let Some(blkio) = r.block_io.as_ref() {
+        if let Some(w) = blkio.weight {
+            if w != 0 {
+                let weight = format!{"default {}", io_weight(w)};
+                write_file(dir, "io.weight", &weight)?;
+            }
+        }
+        if blkio.leaf_weight.is_some() {
+            warn!{"blkio leaf weight is not supported on cgroup v2"};
+        }
+        for d in &blkio.weight_device {
+            if let Some(w) = d.weight {
+                let w = io_weight(w);
+                let weight = format!{"{}:{} {}", d.major, d.minor, w};
+                write_file(dir, "io.weight", &weight)?;
+            }
+        }
+        let limits = &[
+            ("rbps", &blkio.throttle_read_bps_device),
+            ("wbps", &blkio.throttle_write_bps_device),
+            ("riops", &blkio.throttle_read_iops_device),
+            ("wiops", &blkio.throttle_write_iops_device),
+        ];
+        for &(key, devices) in limits {
+            for d in devices {
+                let val = format!{"{}:{} {}={}", d.major, d.minor, key, d.rate};
+                write_file(dir, "io.max", &val)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn hugetlb2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    for h in &r.hugepage_limits {
+        let key = format!{"hugetlb.{}.max", h.page_size};
+        write_file(dir, &key, &h.limit.to_string())?;
+    }
+    Ok(())
+}
+
+fn devices2_apply(r: &LinuxResources, dir: &str) -> Result<()> {
+    let defaults = default_device_rules();
+    let mut rules: Vec<&LinuxDeviceCgroup> = r.devices.iter().collect();
+    rules.extend(defaults.iter());
+    bpf::apply_device_rules(&rules, dir)
+}
+
+// missing files mean the controller isn't available, so they read as zero
+fn read_u64(dir: &str, file: &str) -> u64 {
+    match read_file(dir, file) {
+        Ok(data) => data.trim().parse::<u64>().unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+// reads a value from a file with lines in the form "key value"
+fn read_keyed(dir: &str, file: &str, key: &str) -> u64 {
+    let data = match read_file(dir, file) {
+        Ok(data) => data,
+        Err(_) => return 0,
+    };
+    for line in data.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() == 2 && fields[0] == key {
+            return fields[1].parse::<u64>().unwrap_or(0);
+        }
+    }
+    0
+}
+
+fn parse_device(dev: &str) -> Option<(u64, u64)> {
+    let nums: Vec<&str> = dev.split(':').collect();
+    if nums.len() != 2 {
+        return None;
+    }
+    match (nums[0].parse::<u64>(), nums[1].parse::<u64>()) {
+        (Ok(major), Ok(minor)) => Some((major, minor)),
+        _ => None,
+    }
+}
+
+// lists the hugepage sizes that have a file with the given suffix
+fn hugepage_sizes(dir: &str, suffix: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return result,
+    };
+    for entry in entries {
+        if let Ok(entry) = entry {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("hugetlb.") && name.ends_with(suffix) {
+                let size = &name["hugetlb.".len()..name.len() - suffix.len()];
+                result.push(size.to_string());
+            }
+        }
+    }
+    result
+}
+
+pub fn stats(cgroups_path: &str) -> Stats {
+    if let Some(dir) = unified_dir(cgroups_path) {
+        return unified_stats(&dir);
+    }
+    let mut st = Stats::default();
+    if let Some(dir) = controller_path("memory", cgroups_path) {
+        st.memory.usage = read_u64(&dir, "memory.usage_in_bytes");
+        st.memory.max_usage = read_u64(&dir, "memory.max_usage_in_bytes");
+        st.memory.failcnt = read_u64(&dir, "memory.failcnt");
+        st.memory.limit = read_u64(&dir, "memory.limit_in_bytes");
+    }
+    if let Some(dir) = controller_path("cpuacct", cgroups_path) {
+        st.cpu.total = read_u64(&dir, "cpuacct.usage");
+        if let Ok(data) = read_file(&dir, "cpuacct.usage_percpu") {
+            st.cpu.per_cpu = data.split_whitespace()
+                .filter_map(|v| v.parse::<u64>().ok())
+                .collect();
+        }
+        // cpuacct.stat is in clock ticks
+        let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        if hz > 0 {
+            let ns = 1_000_000_000 / hz;
+            st.cpu.user = read_keyed(&dir, "cpuacct.stat", "user") * ns;
+            st.cpu.kernel = read_keyed(&dir, "cpuacct.stat", "system") * ns;
+        }
+    }
+    if let Some(dir) = controller_path("pids", cgroups_path) {
+        st.pids.current = read_u64(&dir, "pids.current");
+        st.pids.limit = read_u64(&dir, "pids.max");
+    }
+    if let Some(dir) = controller_path("blkio", cgroups_path) {
+        st.blkio.io_service_bytes =
+            blkio_entries(&dir, "blkio.throttle.io_service_bytes");
+        st.blkio.io_serviced =
+            blkio_entries(&dir, "blkio.throttle.io_serviced");
+    }
+    if let Some(dir) = controller_path("hugetlb", cgroups_path) {
+        for size in hugepage_sizes(&dir, ".usage_in_bytes") {
+            let prefix = format!{"hugetlb.{}", size};
+            let h = HugetlbStats {
+                usage: read_u64(&dir, &format!{"{}.usage_in_bytes", prefix}),
+                max_usage: read_u64(
+                    &dir,
+                    &format!{"{}.max_usage_in_bytes", prefix},
+                ),
+                failcnt: read_u64(&dir, &format!{"{}.failcnt", prefix}),
+            };
+            st.hugetlb.insert(size, h);
+        }
+    }
+    st
+}
+
+fn blkio_entries(dir: &str, file: &str) -> Vec<BlkioEntry> {
+    let mut result = Vec::new();
+    let data = match read_file(dir, file) {
+        Ok(data) => data,
+        Err(_) => return result,
+    };
+    // lines are in the form "major:minor op value"
+    for line in data.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            continue;
+        }
+        if let Some((major, minor)) = parse_device(fields[0]) {
+            result.push(BlkioEntry {
+                major: major,
+                minor: minor,
+                op: fields[1].to_string(),
+                value: fields[2].parse::<u64>().unwrap_or(0),
+            });
+        }
+    }
+    result
+}
+
+fn unified_stats(dir: &str) -> Stats {
+    let mut st = Stats::default();
+    st.memory.usage = read_u64(dir, "memory.current");
+    // NOTE: memory.peak only exists on newer kernels
+    st.memory.max_usage = read_u64(dir, "memory.peak");
+    st.memory.failcnt = read_keyed(dir, "memory.events", "max");
+    st.memory.limit = read_u64(dir, "memory.max");
+    // cpu.stat is in microseconds
+    st.cpu.total = read_keyed(dir, "cpu.stat", "usage_usec") * 1000;
+    st.cpu.user = read_keyed(dir, "cpu.stat", "user_usec") * 1000;
+    st.cpu.kernel = read_keyed(dir, "cpu.stat", "system_usec") * 1000;
+    st.pids.current = read_u64(dir, "pids.current");
+    st.pids.limit = read_u64(dir, "pids.max");
+    if let Ok(data) = read_file(dir, "io.stat") {
+        // lines are in the form "major:minor rbytes=x wbytes=y ..."
+        for line in data.lines() {
+            let mut fields = line.split_whitespace();
+            let (major, minor) = match fields.next().and_then(parse_device) {
+                Some(dev) => dev,
+                None => continue,
+            };
+            for field in fields {
+                let kv: Vec<&str> = field.split('=').collect();
+                if kv.len() != 2 {
+                    continue;
+                }
+                let entry = |op: &str| {
+                    BlkioEntry {
+                        major: major,
+                        minor: minor,
+                        op: op.to_string(),
+                        value: kv[1].parse::<u64>().unwrap_or(0),
+                    }
+                };
+                match kv[0] {
+                    "rbytes" => st.blkio.io_service_bytes.push(entry("Read")),
+                    "wbytes" => st.blkio.io_service_bytes.push(entry("Write")),
+                    "rios" => st.blkio.io_serviced.push(entry("Read")),
+                    "wios" => st.blkio.io_serviced.push(entry("Write")),
+                    _ => {}
+                }
+            }
+        }
+    }
+    for size in hugepage_sizes(dir, ".current") {
+        let prefix = format!{"hugetlb.{}", size};
+        let h = HugetlbStats {
+            usage: read_u64(dir, &format!{"{}.current", prefix}),
+            max_usage: 0,
+            failcnt: read_keyed(dir, &format!{"{}.events", prefix}, "max"),
+        };
+        st.hugetlb.insert(size, h);
+    }
+    st
+}
+
+// Notifies about oom kills in a cgroup using memory.oom_control on v1
+// and by watching memory.events on v2. The fd becomes readable when
+// there may have been an oom.
+pub struct OomNotifier {
+    fd: RawFd,
+    dir: String,
+    unified: bool,
+    count: u64,
+}
+
+impl OomNotifier {
+    pub fn new(cgroups_path: &str) -> Result<OomNotifier> {
+        if let Some(dir) = unified_dir(cgroups_path) {
+            let fd = inotify_init1(libc::IN_CLOEXEC)?;
+            let path = CString::new(format!{"{}/memory.events", dir})?;
+            if let Err(e) = inotify_add_watch(fd, &path, libc::IN_MODIFY) {
+                close(fd)?;
+                let chain = || format!("failed to watch {}/memory.events", dir);
+                return Err(e).chain_err(chain);
+            }
+            let count = read_keyed(&dir, "memory.events", "oom_kill");
+            return Ok(OomNotifier {
+                fd: fd,
+                dir: dir,
+                unified: true,
+                count: count,
+            });
+        }
+        let dir = if let Some(dir) = controller_path("memory", cgroups_path) {
+            dir
+        } else {
+            bail!("memory cgroup is not mounted");
+        };
+        let efd = eventfd(0, libc::EFD_CLOEXEC)?;
+        let path = format!{"{}/memory.oom_control", dir};
+        let ofd = match open(&*path, O_RDONLY | O_CLOEXEC, Mode::empty()) {
+            Ok(ofd) => ofd,
+            Err(e) => {
+                close(efd)?;
+                return Err(e).chain_err(|| format!("failed to open {}", path));
+            }
+        };
+        defer!({ let _ = close(ofd); });
+        let control = format!{"{} {}", efd, ofd};
+        if let Err(e) = write_file(&dir, "cgroup.event_control", &control) {
+            close(efd)?;
+            return Err(e);
+        }
+        Ok(OomNotifier {
+            fd: efd,
+            dir: dir,
+            unified: false,
+            count: 0,
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    // must be called when the fd is readable, returns true on oom
+    pub fn read(&mut self) -> Result<bool> {
+        let data: &mut [u8] = &mut [0; 4096];
+        read(self.fd, data)?;
+        if !Path::new(&self.dir).exists() {
+            // the event also fires when the cgroup is removed
+            return Ok(false);
+        }
+        if !self.unified {
+            return Ok(true);
+        }
+        let count = read_keyed(&self.dir, "memory.events", "oom_kill");
+        let oom = count > self.count;
+        self.count = count;
+        Ok(oom)
+    }
+}
+
+impl Drop for OomNotifier {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
@@ -0,0 +1,106 @@
+// A container process that can open /proc/<pid>/exe of railcar while it
+// is entering the container could write to the railcar binary on the
+// host. To prevent that, railcar re-executes itself from a sealed memfd
+// copy of its binary before touching any namespaces. Kernels without
+// memfd get a read-only bind mount of the binary instead, which makes
+// writes through /proc/<pid>/exe fail with EROFS.
+use errors::*;
+use libc;
+use nix::Errno;
+use nix::mount::{mount, umount2, MNT_DETACH, MS_BIND, MS_RDONLY, MS_REMOUNT};
+use nix::unistd::getpid;
+use nix_ext::{fexecve, memfd_create};
+use std::env;
+use std::ffi::CString;
+use std::fs::{File, remove_file};
+use std::io::copy;
+use std::mem::zeroed;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+
+const SELF_EXE: &'static str = "/proc/self/exe";
+// NOTE: set this to skip the copy when the binary is known to be safe,
+//       for example when it is on a read-only filesystem
+pub const NO_CLONE_ENV: &'static str = "RAILCAR_NO_CLONED_BINARY";
+const SEALS: libc::c_int = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK |
+    libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+// NOTE: a binary on a read-only mount can't be written to either, which
+//       also covers the bind mounted clone
+fn is_cloned() -> Result<bool> {
+    let exe = File::open(SELF_EXE)?;
+    // a regular file has no seals, so this fails with EINVAL
+    let seals = unsafe { libc::fcntl(exe.as_raw_fd(), libc::F_GET_SEALS) };
+    if seals != -1 && seals & SEALS == SEALS {
+        return Ok(true);
+    }
+    let mut st: libc::statvfs = unsafe { zeroed() };
+    let res = unsafe { libc::fstatvfs(exe.as_raw_fd(), &mut st) };
+    Errno::result(res)?;
+    Ok(st.f_flag & libc::ST_RDONLY != 0)
+}
+
+fn memfd_clone() -> Result<File> {
+    let name = CString::new("railcar")?;
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let fd = memfd_create(&name, flags)
+        .chain_err(|| "failed to create memfd")?;
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    let mut exe = File::open(SELF_EXE)?;
+    copy(&mut exe, &mut memfd).chain_err(|| "failed to copy binary")?;
+    let res = unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, SEALS) };
+    Errno::result(res).chain_err(|| "failed to seal memfd")?;
+    Ok(memfd)
+}
+
+fn mount_clone() -> Result<File> {
+    let mut tmp = env::temp_dir();
+    tmp.push(format!("railcar-exe.{}", getpid()));
+    let path = tmp.as_path();
+    File::create(path)?;
+    defer!({
+        let _ = remove_file(path);
+    });
+    let none = None::<&str>;
+    mount(Some(SELF_EXE), path, none, MS_BIND, none)?;
+    let flags = MS_BIND | MS_REMOUNT | MS_RDONLY;
+    let result = mount(Some(path), path, none, flags, none)
+        .map_err(Error::from)
+        .and_then(|_| Ok(File::open(path)?));
+    // the open file keeps the detached mount alive
+    umount2(path, MNT_DETACH)?;
+    result
+}
+
+// re-executes the current process from a sealed copy of its binary
+// unless it is already running from one
+pub fn ensure(args: &[CString]) -> Result<()> {
+    if env::var_os(NO_CLONE_ENV).map_or(false, |v| !v.is_empty()) {
+        debug!("{} is set, not cloning binary", NO_CLONE_ENV);
+        return Ok(());
+    }
+    if is_cloned()? {
+        return Ok(());
+    }
+    debug!("re-executing from a sealed copy of the binary");
+    let exe = match memfd_clone() {
+        Ok(f) => f,
+        Err(e) => {
+            debug!("falling back to a read-only mount: {}", e);
+            mount_clone().chain_err(|| {
+                format!("failed to clone binary, set {} to skip", NO_CLONE_ENV)
+            })?
+        }
+    };
+    let mut vars = Vec::new();
+    for (k, v) in env::vars_os() {
+        let mut var = k.as_bytes().to_vec();
+        var.push(b'=');
+        var.extend_from_slice(v.as_bytes());
+        vars.push(CString::new(var)?);
+    }
+    let fd = exe.into_raw_fd();
+    fexecve(fd, args, &vars).chain_err(|| "failed to exec cloned binary")?;
+    // should never reach here
+    Ok(())
+}
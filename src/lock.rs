@@ -0,0 +1,72 @@
+// Per container locking of the state dir. We use fcntl locks because,
+// unlike flock, they are not inherited by the processes that we fork.
+use errors::*;
+use libc;
+use nix::Errno;
+use nix::fcntl::{fcntl, open, O_CLOEXEC, O_CREAT, O_RDWR};
+use nix::fcntl::FcntlArg::F_SETLKW;
+use nix::sys::stat::{fstat, stat, Mode};
+use nix::unistd::close;
+use std::fs::remove_file;
+use std::mem::zeroed;
+use std::os::unix::io::RawFd;
+
+pub struct Lock {
+    fd: RawFd,
+    path: String,
+}
+
+impl Lock {
+    // blocks until the lock is held
+    pub fn acquire(path: &str) -> Result<Lock> {
+        loop {
+            let flags = O_RDWR | O_CREAT | O_CLOEXEC;
+            let mode = Mode::from_bits_truncate(0o600);
+            let fd = open(path, flags, mode).chain_err(
+                || format!("failed to open lock {}", path),
+            )?;
+            let lock = Lock {
+                fd: fd,
+                path: path.to_string(),
+            };
+            let mut fl: libc::flock = unsafe { zeroed() };
+            fl.l_type = libc::F_WRLCK as libc::c_short;
+            fl.l_whence = libc::SEEK_SET as libc::c_short;
+            loop {
+                match fcntl(fd, F_SETLKW(&fl)) {
+                    Ok(_) => break,
+                    Err(e) => {
+                        if e.errno() != Errno::EINTR {
+                            let msg = format!("failed to lock {}", path);
+                            return Err(e).chain_err(|| msg);
+                        }
+                    }
+                }
+            }
+            // NOTE: the lock file is removed by delete, so make sure that
+            //       the file we locked is still the one at path
+            let held = fstat(fd)?;
+            if let Ok(current) = stat(path) {
+                if current.st_dev == held.st_dev &&
+                    current.st_ino == held.st_ino
+                {
+                    debug!("acquired lock {}", path);
+                    return Ok(lock);
+                }
+            }
+        }
+    }
+
+    // removes the lock file while it is still held
+    pub fn remove(&self) -> Result<()> {
+        remove_file(&self.path)
+            .chain_err(|| format!("failed to remove lock {}", self.path))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // closing the fd releases the lock
+        let _ = close(self.fd);
+    }
+}
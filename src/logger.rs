@@ -1,7 +1,61 @@
 use log::{Log, LogRecord, LogLevel, LogMetadata};
+use nix::Result;
+use nix::fcntl::{fcntl, FcntlArg};
+use std::fs::File;
 use std::io::{Write, stderr};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct SimpleLogger;
+lazy_static! {
+    // NOTE: messages go to stderr if there is no log file. The file is
+    //       kept outside of the logger so that its fd can be moved.
+    static ref FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+pub fn set_file(f: File) {
+    if let Ok(mut file) = FILE.lock() {
+        *file = Some(f);
+    }
+}
+
+// moves the log file to an fd of at least min so that it isn't replaced
+// by the fds that are installed for the container process
+pub fn move_file(min: RawFd) -> Result<()> {
+    if let Ok(mut file) = FILE.lock() {
+        if let Some(ref mut f) = *file {
+            if f.as_raw_fd() < min {
+                let fd = fcntl(f.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(min))?;
+                // the old fd is closed when the old file is dropped
+                *f = unsafe { File::from_raw_fd(fd) };
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct SimpleLogger {
+    pub json: bool,
+}
+
+impl SimpleLogger {
+    fn format(&self, record: &LogRecord) -> String {
+        let msg = record.args().to_string();
+        self.format_line(record.level(), &msg, SystemTime::now())
+    }
+
+    fn format_line(&self, level: LogLevel, msg: &str, t: SystemTime) -> String {
+        if !self.json {
+            return format!("{} - {}", level, msg);
+        }
+        format!(
+            "{{\"level\":\"{}\",\"msg\":\"{}\",\"time\":\"{}\"}}",
+            level.to_string().to_lowercase(),
+            escape(msg),
+            format_time(t)
+        )
+    }
+}
 
 impl Log for SimpleLogger {
     fn enabled(&self, metadata: &LogMetadata) -> bool {
@@ -10,12 +64,122 @@ impl Log for SimpleLogger {
 
     fn log(&self, record: &LogRecord) {
         if self.enabled(record.metadata()) {
-            let _ = writeln!(
-                &mut stderr(),
-                "{} - {}",
-                record.level(),
-                record.args()
-            );
+            let line = self.format(record);
+            if let Ok(mut file) = FILE.lock() {
+                if let Some(ref mut f) = *file {
+                    let _ = writeln!(f, "{}", line);
+                    return;
+                }
+            }
+            let _ = writeln!(&mut stderr(), "{}", line);
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
         }
     }
+    out
+}
+
+// formats a time as RFC 3339 in UTC, trimming trailing zeros from the
+// fraction like go does
+pub fn format_time(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // convert days since the epoch to a civil date
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let mut fraction = format!(".{:09}", d.subsec_nanos());
+    while fraction.ends_with('0') {
+        fraction.pop();
+    }
+    if fraction == "." {
+        fraction.clear();
+    }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        fraction
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64, nanos: u32) -> SystemTime {
+        UNIX_EPOCH + Duration::new(secs, nanos)
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("plain text"), "plain text");
+        assert_eq!(escape("say \"hi\""), "say \\\"hi\\\"");
+        assert_eq!(escape("C:\\dir"), "C:\\\\dir");
+        assert_eq!(escape("a\nb\r\tc"), "a\\nb\\r\\tc");
+        assert_eq!(escape("\u{0}\u{1b}[0m\u{7f}"), "\\u0000\\u001b[0m\u{7f}");
+        assert_eq!(escape("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(at(951782400, 0)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(at(1709210096, 0)), "2024-02-29T12:34:56Z");
+        // 2100 is not a leap year
+        assert_eq!(format_time(at(4107542399, 0)), "2100-02-28T23:59:59Z");
+        assert_eq!(format_time(at(4107542400, 0)), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(format_time(at(0, 500_000_000)), "1970-01-01T00:00:00.5Z");
+        assert_eq!(format_time(at(0, 1_000)), "1970-01-01T00:00:00.000001Z");
+        assert_eq!(
+            format_time(at(2147483648, 123_456_789)),
+            "2038-01-19T03:14:08.123456789Z"
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let t = at(1709210096, 250_000_000);
+        let text = SimpleLogger { json: false };
+        let line = text.format_line(LogLevel::Warn, "a \"b\"", t);
+        assert_eq!(line, "WARN - a \"b\"");
+        let json = SimpleLogger { json: true };
+        assert_eq!(
+            json.format_line(LogLevel::Error, "bad \"value\"\n", t),
+            "{\"level\":\"error\",\"msg\":\"bad \\\"value\\\"\\n\",\
+             \"time\":\"2024-02-29T12:34:56.25Z\"}"
+        );
+    }
 }
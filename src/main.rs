@@ -23,21 +23,32 @@ extern crate scopeguard;
 extern crate seccomp_sys;
 extern crate oci;
 
+mod bpf;
 mod capabilities;
 mod cgroups;
+mod cloned;
+mod digest;
 mod errors;
+mod lock;
 mod logger;
 mod sync;
 mod mounts;
 mod seccomp;
 mod selinux;
+mod procfs;
+mod resolve;
 mod signals;
+mod spec;
+mod systemd;
+mod tty;
 mod nix_ext;
 
 use clap::{Arg, ArgMatches, App, AppSettings, SubCommand};
 use errors::*;
 use lazy_static::initialize;
-use nix::fcntl::{open, OFlag, O_RDWR, O_RDONLY, O_WRONLY, O_CLOEXEC, O_NOCTTY};
+use lock::Lock;
+use nix::fcntl::{open, OFlag, O_RDWR, O_RDONLY, O_WRONLY, O_APPEND};
+use nix::fcntl::{O_CLOEXEC, O_NOCTTY, FD_CLOEXEC, fcntl, FcntlArg};
 use nix::poll::{poll, PollFd, POLLIN, POLLHUP, POLLNVAL, EventFlags};
 use nix::sched::{setns, unshare, CloneFlags};
 use nix::sched::{CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWUSER, CLONE_NEWUTS};
@@ -45,20 +56,29 @@ use nix::sched::{CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWCGROUP};
 use nix::sys::signal::{SigSet, Signal};
 use nix::sys::stat::{Mode, fstat};
 use nix::sys::wait::{waitpid, WaitStatus, WNOHANG};
-use nix::unistd::{close, fork, ForkResult, pipe2, read, write, dup2, setsid};
+use nix::unistd::{close, fork, ForkResult, pipe2, read, write, dup2};
 use nix::unistd::{setresuid, setresgid, chdir, sethostname, execvp, getpid};
+use nix::unistd::{getcwd, getuid, geteuid};
 use nix::Errno;
-use nix_ext::{setgroups, setrlimit, clearenv, putenv};
-use oci::{Spec, Linux, LinuxIDMapping, LinuxRlimit};
+use nix_ext::{setgroups, setrlimit, clearenv, putenv, send_fd};
+use nix_ext::{pidfd_open, pidfd_getfd, user_name};
+use nix_ext::{close_range, CLOSE_RANGE_CLOEXEC};
+use oci::{Spec, Linux, LinuxIDMapping, LinuxResources, LinuxRlimit};
 use oci::{LinuxDevice, LinuxDeviceType};
+use std::cmp;
 use std::collections::HashMap;
 use std::ffi::CString;
-use std::fs::{File, create_dir, create_dir_all, remove_dir_all, canonicalize};
+use std::fs::{File, OpenOptions, create_dir, create_dir_all, remove_dir_all};
+use std::fs::{canonicalize, metadata, read_dir, rename, symlink_metadata};
 use std::io::{Read, Write};
-use std::os::unix::fs::symlink;
-use std::os::unix::io::{RawFd, FromRawFd};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::os::unix::io::{AsRawFd, RawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
 use std::result::Result as StdResult;
-use sync::Cond;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+use sync::{Cond, FdSocket, PidSocket};
 
 lazy_static! {
     static ref DEFAULT_DEVICES: Vec<LinuxDevice> = {
@@ -138,6 +158,11 @@ lazy_static! {
 const CONFIG: &'static str = "config.json";
 const INIT_PID: &'static str = "init.pid";
 const PROCESS_PID: &'static str = "process.pid";
+const STATE: &'static str = "state.json";
+
+// milliseconds to wait for killed processes to leave the cgroup
+const KILL_TIMEOUT: u64 = 10000;
+const KILL_INTERVAL: u64 = 10;
 
 #[cfg(feature = "nightly")]
 static mut ARGC: isize = 0 as isize;
@@ -193,6 +218,29 @@ fn get_args() -> Vec<String> {
     std::env::args().collect()
 }
 
+// NOTE: re-executing needs the arguments exactly as they were passed,
+//       which may not be valid utf-8
+#[cfg(feature = "nightly")]
+fn get_raw_args() -> Result<Vec<CString>> {
+    let mut args = Vec::new();
+    unsafe {
+        for i in 0..ARGC {
+            args.push(std::ffi::CStr::from_ptr(*ARGV.offset(i)).to_owned());
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(not(feature = "nightly"))]
+fn get_raw_args() -> Result<Vec<CString>> {
+    use std::os::unix::ffi::OsStrExt;
+    let mut args = Vec::new();
+    for arg in std::env::args_os() {
+        args.push(CString::new(arg.as_bytes())?);
+    }
+    Ok(args)
+}
+
 fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
     let pid = getpid();
@@ -221,7 +269,25 @@ fn id_validator(val: String) -> StdResult<(), String> {
     Ok(())
 }
 
+// NOTE: rootless containers are created by an unprivileged user, who
+//       generally can't write to /run
+fn is_rootless() -> bool {
+    geteuid() != 0
+}
+
+fn default_root() -> String {
+    if is_rootless() {
+        if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+            if !dir.is_empty() {
+                return format!("{}/railcar", dir);
+            }
+        }
+    }
+    "/run/railcar".to_string()
+}
+
 fn run() -> Result<()> {
+    let root = default_root();
     let id_arg = Arg::with_name("id")
         .required(true)
         .takes_value(true)
@@ -237,11 +303,19 @@ fn run() -> Result<()> {
         .long("pid-file")
         .short("p")
         .takes_value(true);
+    let console_socket_arg = Arg::with_name("console-socket")
+        .help("Unix socket to send the pty master to")
+        .long("console-socket")
+        .takes_value(true);
+    let preserve_fds_arg = Arg::with_name("preserve-fds")
+        .help("Number of fds after stdio to pass to the process")
+        .long("preserve-fds")
+        .takes_value(true);
 
     let matches = App::new("Railcar")
         .about("Railcar - run conatiner from oci runtime spec")
         .setting(AppSettings::ColoredHelp)
-        .author(crate_authors!("\n"))
+        .author(env!("CARGO_PKG_AUTHORS"))
         .setting(AppSettings::SubcommandRequired)
         .version(crate_version!())
         .arg(
@@ -258,16 +332,23 @@ fn run() -> Result<()> {
         )
         .arg(
             Arg::with_name("log")
-                .help("Compatibility (ignored)")
+                .help("Log to file instead of stderr")
                 .long("log")
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("log-format")
-                .help("Compatibility (ignored)")
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("Format of log messages")
                 .long("log-format")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("systemd-cgroup")
+                .help("Use systemd to create cgroups (slice:prefix:name)")
+                .long("systemd-cgroup"),
+        )
         .arg(
             Arg::with_name("n")
                 .help("Do not create an init process")
@@ -282,7 +363,7 @@ fn run() -> Result<()> {
         )
         .arg(
             Arg::with_name("r")
-                .default_value("/run/railcar")
+                .default_value(&root)
                 .help("Dir for state")
                 .long("root")
                 .short("r")
@@ -294,6 +375,8 @@ fn run() -> Result<()> {
                 .arg(&id_arg)
                 .arg(&bundle_arg)
                 .arg(&pid_arg)
+                .arg(&console_socket_arg)
+                .arg(&preserve_fds_arg)
                 .about("Run a container"),
         )
         .subcommand(
@@ -307,16 +390,76 @@ fn run() -> Result<()> {
                         .help("console to use")
                         .long("console")
                         .short("c")
-                        .takes_value(true),
+                        .takes_value(true)
+                        .conflicts_with("console-socket"),
                 )
+                .arg(&console_socket_arg)
+                .arg(&preserve_fds_arg)
                 .about("Create a container (to be started later)"),
         )
+        .subcommand(
+            SubCommand::with_name("spec")
+                .setting(AppSettings::ColoredHelp)
+                .arg(&bundle_arg)
+                .arg(
+                    Arg::with_name("rootless")
+                        .help("Generate a configuration for rootless use")
+                        .long("rootless"),
+                )
+                .about("Create a new specification file"),
+        )
         .subcommand(
             SubCommand::with_name("start")
                 .setting(AppSettings::ColoredHelp)
                 .arg(&id_arg)
                 .about("Start a (previously created) container"),
         )
+        .subcommand(
+            SubCommand::with_name("events")
+                .setting(AppSettings::ColoredHelp)
+                .arg(&id_arg)
+                .arg(
+                    Arg::with_name("stats")
+                        .help("Display stats once and exit")
+                        .long("stats"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .default_value("5")
+                        .help("Seconds between stats")
+                        .long("interval")
+                        .takes_value(true),
+                )
+                .about("Display events and stats for a container"),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .setting(AppSettings::ColoredHelp)
+                .setting(AppSettings::TrailingVarArg)
+                .arg(&id_arg)
+                .arg(&pid_arg)
+                .arg(&preserve_fds_arg)
+                .arg(
+                    Arg::with_name("d")
+                        .help("Detach from the executed process")
+                        .long("detach")
+                        .short("d"),
+                )
+                .arg(
+                    Arg::with_name("process")
+                        .help("Path to process.json describing the process")
+                        .long("process")
+                        .takes_value(true)
+                        .conflicts_with("args"),
+                )
+                .arg(
+                    Arg::with_name("args")
+                        .multiple(true)
+                        .required_unless("process")
+                        .help("Command and arguments to execute"),
+                )
+                .about("Execute a process in a (previously created) container"),
+        )
         .subcommand(
             SubCommand::with_name("state")
                 .setting(AppSettings::ColoredHelp)
@@ -331,10 +474,9 @@ fn run() -> Result<()> {
                 .arg(&id_arg)
                 .arg(
                     Arg::with_name("a")
-                        .help("Compatibility (ignored)")
+                        .help("Signal all processes in the container")
                         .long("all")
-                        .short("a")
-                        .takes_value(true),
+                        .short("a"),
                 )
                 .arg(
                     Arg::with_name("signal")
@@ -349,15 +491,133 @@ fn run() -> Result<()> {
             SubCommand::with_name("delete")
                 .setting(AppSettings::ColoredHelp)
                 .arg(&id_arg)
+                .arg(
+                    Arg::with_name("f")
+                        .help("Kill the container if it is still running")
+                        .long("force")
+                        .short("f"),
+                )
                 .about("Delete a (previously created) container"),
         )
+        .subcommand(
+            SubCommand::with_name("pause")
+                .setting(AppSettings::ColoredHelp)
+                .arg(&id_arg)
+                .about(
+                    "Suspend all processes in a (previously created) container",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .setting(AppSettings::ColoredHelp)
+                .arg(&id_arg)
+                .about(
+                    "Resume all processes in a (previously paused) container",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .setting(AppSettings::ColoredHelp)
+                .arg(&id_arg)
+                .arg(
+                    Arg::with_name("resources")
+                        .help("Path to json file with the resources to update")
+                        .long("resources")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("memory")
+                        .help("Memory limit in bytes")
+                        .long("memory")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("memory-reservation")
+                        .help("Memory soft limit in bytes")
+                        .long("memory-reservation")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("memory-swap")
+                        .help("Total memory plus swap limit in bytes")
+                        .long("memory-swap")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpu-shares")
+                        .help("Relative cpu weight")
+                        .long("cpu-shares")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpu-quota")
+                        .help("Cpu time in microseconds allowed per period")
+                        .long("cpu-quota")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpu-period")
+                        .help("Cpu period in microseconds")
+                        .long("cpu-period")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpuset-cpus")
+                        .help("Cpus the container may use")
+                        .long("cpuset-cpus")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpuset-mems")
+                        .help("Memory nodes the container may use")
+                        .long("cpuset-mems")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("pids-limit")
+                        .help("Maximum number of pids")
+                        .long("pids-limit")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("blkio-weight")
+                        .help("Relative block io weight")
+                        .long("blkio-weight")
+                        .takes_value(true),
+                )
+                .about(
+                    "Update resources of a (previously created) container",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("f")
+                        .default_value("table")
+                        .possible_values(&["table", "json"])
+                        .help("Output format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("q")
+                        .help("Only display container ids")
+                        .long("quiet")
+                        .short("q"),
+                )
+                .about("List containers managed by railcar"),
+        )
         .subcommand(
             SubCommand::with_name("ps")
                 .setting(AppSettings::ColoredHelp)
                 .arg(&id_arg)
                 .arg(
                     Arg::with_name("f")
-                        .help("Compatibility (ignored)")
+                        .default_value("json")
+                        .possible_values(&["table", "json"])
+                        .help("Output format")
                         .long("format")
                         .short("f")
                         .takes_value(true),
@@ -371,26 +631,75 @@ fn run() -> Result<()> {
         _ => log::LogLevelFilter::Trace,
     };
 
+    // NOTE: if the log file can't be opened, we still set up the logger
+    //       so the error is reported on stderr
+    let mut log_err = None;
+    if let Some(path) = matches.value_of("log") {
+        let f = OpenOptions::new().create(true).append(true).open(path);
+        match f {
+            Ok(f) => logger::set_file(f),
+            Err(e) => log_err = Some((path, e)),
+        }
+    }
+    let json = matches.value_of("log-format") == Some("json");
     let _ = log::set_logger(|max_log_level| {
         max_log_level.set(level);
-        Box::new(logger::SimpleLogger)
+        Box::new(logger::SimpleLogger { json: json })
     });
+    if let Some((path, e)) = log_err {
+        let msg = format!("failed to open log file {}", path);
+        return Err(e).chain_err(|| msg);
+    }
+
+    // spec doesn't need the state dir, which may not be writable
+    if let ("spec", Some(spec_matches)) = matches.subcommand() {
+        return cmd_spec(spec_matches);
+    }
+
+    // only commands that enter the container through run_container need
+    // to be protected
+    match matches.subcommand_name() {
+        Some("create") | Some("start") | Some("exec") | Some("run") => {
+            cloned::ensure(&get_raw_args()?)?;
+        }
+        _ => {}
+    }
 
     let state_dir = matches.value_of("r").unwrap().to_string();
     debug!("ensuring railcar state dir {}", &state_dir);
     let chain = || format!("ensuring railcar state dir {} failed", &state_dir);
     create_dir_all(&state_dir).chain_err(chain)?;
 
+    let systemd_cgroup = matches.is_present("systemd-cgroup");
     match matches.subcommand() {
         ("create", Some(create_matches)) => {
             cmd_create(
                 create_matches.value_of("id").unwrap(),
                 &state_dir,
                 create_matches,
+                systemd_cgroup,
             )
         }
         ("delete", Some(delete_matches)) => {
-            cmd_delete(delete_matches.value_of("id").unwrap(), &state_dir)
+            cmd_delete(
+                delete_matches.value_of("id").unwrap(),
+                &state_dir,
+                delete_matches,
+            )
+        }
+        ("events", Some(events_matches)) => {
+            cmd_events(
+                events_matches.value_of("id").unwrap(),
+                &state_dir,
+                events_matches,
+            )
+        }
+        ("exec", Some(exec_matches)) => {
+            cmd_exec(
+                exec_matches.value_of("id").unwrap(),
+                &state_dir,
+                exec_matches,
+            )
         }
         ("kill", Some(kill_matches)) => {
             cmd_kill(
@@ -399,11 +708,29 @@ fn run() -> Result<()> {
                 kill_matches,
             )
         }
+        ("list", Some(list_matches)) => cmd_list(&state_dir, list_matches),
+        ("pause", Some(pause_matches)) => {
+            cmd_pause(pause_matches.value_of("id").unwrap(), &state_dir)
+        }
+        ("resume", Some(resume_matches)) => {
+            cmd_resume(resume_matches.value_of("id").unwrap(), &state_dir)
+        }
+        ("update", Some(update_matches)) => {
+            cmd_update(
+                update_matches.value_of("id").unwrap(),
+                &state_dir,
+                update_matches,
+            )
+        }
         ("ps", Some(ps_matches)) => {
-            cmd_ps(ps_matches.value_of("id").unwrap(), &state_dir)
+            cmd_ps(ps_matches.value_of("id").unwrap(), &state_dir, ps_matches)
         }
         ("run", Some(run_matches)) => {
-            cmd_run(run_matches.value_of("id").unwrap(), run_matches)
+            cmd_run(
+                run_matches.value_of("id").unwrap(),
+                run_matches,
+                systemd_cgroup,
+            )
         }
         ("start", Some(start_matches)) => {
             cmd_start(
@@ -436,10 +763,108 @@ fn state(id: &str, status: &str, pid: i32, bundle: &str) -> oci::State {
     }
 }
 
+#[inline]
+fn cgroups_path(id: &str, linux: &Linux) -> String {
+    if linux.cgroups_path == "" {
+        format!{"/{}", id}
+    } else if systemd::is_systemd_path(&linux.cgroups_path) {
+        // NOTE: an invalid path is returned as is and rejected later
+        //       because it isn't absolute
+        systemd::expand_path(&linux.cgroups_path)
+            .unwrap_or_else(|_| linux.cgroups_path.clone())
+    } else {
+        linux.cgroups_path.clone()
+    }
+}
+
+// makes sure the cgroups path matches the cgroup driver in use
+fn check_cgroups_path(id: &str, spec: &mut Spec, systemd: bool) -> Result<()> {
+    let linux = spec.linux.as_mut().unwrap();
+    if systemd {
+        if linux.cgroups_path == "" {
+            linux.cgroups_path = format!("system.slice:railcar:{}", id);
+        }
+        systemd::expand_path(&linux.cgroups_path)?;
+    } else if systemd::is_systemd_path(&linux.cgroups_path) {
+        let msg = "systemd cgroups path requires --systemd-cgroup";
+        return Err(ErrorKind::InvalidSpec(msg.to_string()).into());
+    }
+    Ok(())
+}
+
+// takes over fds of the init process, which holds the stdio, listen fds
+// and preserved fds that were given to create. The fds are returned as
+// (target, fd) pairs so they can be installed in the container process.
+fn inherit_fds(
+    init_pid: i32,
+    targets: &[RawFd],
+) -> Result<Vec<(RawFd, RawFd)>> {
+    // NOTE: the new fds are kept above every target so that installing
+    //       one of them never replaces another
+    let min = targets.iter().max().map_or(0, |t| t + 1);
+    // NOTE: pidfd_getfd gives us the same open file, so offsets and
+    //       sockets work as expected. Older kernels fall back to
+    //       reopening the files through proc.
+    let pidfd = pidfd_open(init_pid).ok();
+    let mut fds = Vec::new();
+    for &target in targets {
+        let new = match pidfd.map(|p| pidfd_getfd(p, target)) {
+            Some(Ok(new)) => new,
+            _ => {
+                let path = format!("/proc/{}/fd/{}", init_pid, target);
+                let flags = match target {
+                    0 => O_RDONLY,
+                    1 | 2 => O_WRONLY | O_APPEND,
+                    _ => O_RDWR,
+                };
+                match open(&*path, flags | O_CLOEXEC, Mode::empty()) {
+                    Ok(new) => new,
+                    Err(e) => {
+                        warn!("could not reopen {}: {}", path, e);
+                        continue;
+                    }
+                }
+            }
+        };
+        let fd = fcntl(new, FcntlArg::F_DUPFD_CLOEXEC(min))
+            .chain_err(|| format!("could not dup {}", target))?;
+        close(new)?;
+        fds.push((target, fd));
+    }
+    if let Some(p) = pidfd {
+        close(p)?;
+    }
+    Ok(fds)
+}
+
+// returns fd or a duplicate of it that is at least min
+fn move_fd(fd: RawFd, min: RawFd) -> Result<RawFd> {
+    if fd >= min {
+        return Ok(fd);
+    }
+    let new = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(min))
+        .chain_err(|| format!("could not dup {}", fd))?;
+    close(fd)?;
+    Ok(new)
+}
+
+// installs fds from inherit_fds at the numbers they had in init
+fn install_fds(fds: &[(RawFd, RawFd)]) -> Result<()> {
+    for &(target, fd) in fds {
+        dup2(fd, target).chain_err(|| format!("could not dup {}", target))?;
+        close(fd)?;
+    }
+    Ok(())
+}
+
 // must be in instance_dir
 fn get_init_pid() -> Result<(i32)> {
+    read_pid(INIT_PID)
+}
+
+fn read_pid(path: &str) -> Result<(i32)> {
     let mut pid = -1;
-    if let Ok(mut f) = File::open(INIT_PID) {
+    if let Ok(mut f) = File::open(path) {
         let mut result = String::new();
         f.read_to_string(&mut result)?;
         if let Ok(process_pid) = result.parse::<i32>() {
@@ -449,33 +874,121 @@ fn get_init_pid() -> Result<(i32)> {
     Ok(pid)
 }
 
-fn state_from_dir(id: &str, state_dir: &str) -> Result<(oci::State)> {
+// NOTE: a recorded start time of zero means that it is unknown, so only
+//       the pid is checked
+fn process_alive(pid: i32, start_time: u64) -> bool {
+    signals::signal_started(pid, start_time, None).is_ok()
+}
+
+// NOTE: a short lived process may already have exited, in which case its
+//       start time is unknown
+fn start_time(pid: i32) -> u64 {
+    match procfs::stat(pid) {
+        Ok(stat) => stat.start_time,
+        Err(e) => {
+            debug!("could not get start time of {}: {}", pid, e);
+            0
+        }
+    }
+}
+
+// must be in instance_dir or the bundle
+fn bundle_dir() -> Result<String> {
+    if let Ok(record) = oci::StateRecord::load(STATE) {
+        return Ok(record.bundle);
+    }
+    Ok(getcwd()?.to_string_lossy().into_owned())
+}
+
+fn save_record(record: &oci::StateRecord) -> Result<()> {
+    let data = record.to_string().chain_err(|| "invalid state record")?;
+    write_atomic(STATE, data.as_bytes())
+}
+
+// NOTE: the lock file lives next to the instance dir so that it can be
+//       held while the dir is created or removed. Unless we are creating
+//       the instance, the lock file is removed if the instance is missing.
+fn lock_instance(id: &str, state_dir: &str, create: bool) -> Result<Lock> {
+    let lock = Lock::acquire(&format!("{}/{}.lock", state_dir, id))?;
+    if !create && metadata(instance_dir(id, state_dir)).is_err() {
+        lock.remove()?;
+    }
+    Ok(lock)
+}
+
+// writes to a temporary file and renames it so that readers never see
+// a partially written file
+fn write_atomic(path: &str, data: &[u8]) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut f = File::create(&tmp)
+            .chain_err(|| format!("failed to create {}", tmp))?;
+        f.write_all(data)?;
+        f.sync_all()?;
+    }
+    rename(&tmp, path).chain_err(|| format!("failed to rename {}", tmp))?;
+    Ok(())
+}
+
+fn save_atomic(spec: &Spec, path: &str) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    spec.save(&tmp).chain_err(|| format!("failed to save {}", tmp))?;
+    rename(&tmp, path).chain_err(|| format!("failed to rename {}", tmp))?;
+    Ok(())
+}
+
+fn chdir_instance(id: &str, state_dir: &str) -> Result<()> {
     let dir = instance_dir(id, state_dir);
     chdir(&*dir).chain_err(
         || format!("instance {} doesn't exist", id),
     )?;
+    Ok(())
+}
+
+// NOTE: this doesn't change the working directory, so it can be used to
+//       look at multiple instances
+fn state_from_dir(id: &str, state_dir: &str) -> Result<(oci::State)> {
+    let dir = instance_dir(id, state_dir);
+    metadata(&dir).chain_err(
+        || format!("instance {} doesn't exist", id),
+    )?;
     let mut status = "creating";
-    let mut root = String::new();
-    let pid = get_init_pid()?;
-    if let Ok(spec) = Spec::load(CONFIG) {
-        root = spec.root.path.to_owned();
+    let mut bundle = String::new();
+    let pid = read_pid(&format!("{}/{}", dir, INIT_PID))?;
+    let record = oci::StateRecord::load(&format!("{}/{}", dir, STATE)).ok();
+    if let Ok(spec) = Spec::load(&format!("{}/{}", dir, CONFIG)) {
+        bundle = match record {
+            Some(ref r) => r.bundle.to_owned(),
+            None => spec.root.path.to_owned(),
+        };
         status = "created";
-        if let Ok(mut f) = File::open(PROCESS_PID) {
+        if let Ok(mut f) = File::open(format!("{}/{}", dir, PROCESS_PID)) {
             status = "running";
             let mut result = String::new();
             f.read_to_string(&mut result)?;
             if let Ok(process_pid) = result.parse::<i32>() {
-                if signals::signal_process(process_pid, None).is_err() {
+                let start_time =
+                    record.as_ref().map_or(0, |r| r.process_start_time);
+                if !process_alive(process_pid, start_time) {
                     status = "stopped";
+                } else if let Some(ref linux) = spec.linux {
+                    if cgroups::is_frozen(&cgroups_path(id, linux)) {
+                        status = "paused";
+                    }
                 }
             } else {
                 warn!("invalid process pid: {}", result);
             }
+        } else if let Some(ref r) = record {
+            // the container can never start if init is gone
+            if !process_alive(r.pid, r.start_time) {
+                status = "stopped";
+            }
         } else {
             warn!("could not open process pid");
         }
     }
-    let st = state(id, status, pid, &root);
+    let st = state(id, status, pid, &bundle);
     Ok(st)
 }
 
@@ -487,21 +1000,149 @@ fn cmd_state(id: &str, state_dir: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_create(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
+fn cmd_list(state_dir: &str, matches: &ArgMatches) -> Result<()> {
+    debug!("Performing list");
+    let mut containers = Vec::new();
+    let entries = read_dir(state_dir)
+        .chain_err(|| format!("failed to read {}", state_dir))?;
+    for entry in entries {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_dir() {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().into_owned();
+        let st = match state_from_dir(&id, state_dir) {
+            Ok(st) => st,
+            Err(e) => {
+                warn!("failed to get state of {}: {}", id, e);
+                continue;
+            }
+        };
+        let path = format!("{}/{}/{}", state_dir, id, STATE);
+        let (created, uid) = match oci::StateRecord::load(&path) {
+            Ok(r) => (r.created, r.owner),
+            Err(_) => {
+                // containers created before the state record existed
+                let t = meta.created().or_else(|_| meta.modified())?;
+                (logger::format_time(t), meta.uid())
+            }
+        };
+        let owner = match user_name(uid) {
+            Some(name) => name,
+            None => uid.to_string(),
+        };
+        containers.push(oci::ContainerState {
+            version: st.version,
+            id: st.id,
+            pid: st.pid,
+            status: st.status,
+            bundle: st.bundle,
+            created: created,
+            owner: owner,
+        });
+    }
+    containers.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if matches.is_present("q") {
+        for c in &containers {
+            println!("{}", c.id);
+        }
+        return Ok(());
+    }
+    if matches.value_of("f") == Some("json") {
+        let out = oci::serialize::to_string(&containers)
+            .chain_err(|| "failed to serialize containers")?;
+        println!("{}", out);
+        return Ok(());
+    }
+    let mut rows = vec![
+        vec![
+            "ID".to_string(),
+            "PID".to_string(),
+            "STATUS".to_string(),
+            "BUNDLE".to_string(),
+            "CREATED".to_string(),
+            "OWNER".to_string(),
+        ],
+    ];
+    for c in containers {
+        rows.push(vec![
+            c.id,
+            c.pid.to_string(),
+            c.status,
+            c.bundle,
+            c.created,
+            c.owner,
+        ]);
+    }
+    print_table(&rows);
+    Ok(())
+}
+
+fn print_table(rows: &[Vec<String>]) {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (i, col) in row.iter().enumerate() {
+            if i == widths.len() {
+                widths.push(0);
+            }
+            widths[i] = std::cmp::max(widths[i], col.len());
+        }
+    }
+    for row in rows {
+        let mut line = String::new();
+        for (i, col) in row.iter().enumerate() {
+            if i + 1 == row.len() {
+                line.push_str(col);
+            } else {
+                line.push_str(&format!("{:1$}   ", col, widths[i]));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+fn cmd_spec(matches: &ArgMatches) -> Result<()> {
+    debug!("Performing spec");
+    let bundle = matches.value_of("bundle").unwrap();
+    let path = format!("{}/{}", bundle, CONFIG);
+    if symlink_metadata(&path).is_ok() {
+        bail!("{} already exists, remove it first", path);
+    }
+    let spec = spec::default_spec(matches.is_present("rootless"));
+    spec.save(&path).chain_err(|| format!("failed to save {}", path))?;
+    Ok(())
+}
+
+fn cmd_create(
+    id: &str,
+    state_dir: &str,
+    matches: &ArgMatches,
+    systemd_cgroup: bool,
+) -> Result<()> {
     debug!("Performing create");
     let bundle = matches.value_of("bundle").unwrap();
     chdir(&*bundle).chain_err(
         || format!("failed to chdir to {}", bundle),
     )?;
-    let spec = Spec::load(CONFIG).chain_err(
+    let mut spec = Spec::load(CONFIG).chain_err(
         || format!("failed to load {}", CONFIG),
     )?;
+    check_cgroups_path(id, &mut spec, systemd_cgroup)?;
+    let socket = console_socket_path(matches)?;
+    let created = logger::format_time(SystemTime::now());
+    let bundle_path = getcwd()?.to_string_lossy().into_owned();
+    let mut data = Vec::new();
+    File::open(CONFIG)?.read_to_end(&mut data)?;
+    let config_digest = digest::sha256(&data);
 
     let rootfs = canonicalize(&spec.root.path)
         .chain_err(|| format!{"failed to find root path {}", &spec.root.path})?
         .to_string_lossy()
         .into_owned();
 
+    let _lock = lock_instance(id, state_dir, true)?;
     let dir = instance_dir(id, state_dir);
     debug!("creating state dir {}", &dir);
     if let Err(e) = create_dir(&dir) {
@@ -522,18 +1163,51 @@ fn cmd_create(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
         symlink(&console, lnk)?;
     }
     let pidfile = matches.value_of("p").unwrap_or_default();
+    let preserve_fds = get_preserve_fds(matches)?;
+    let (listen_fds, listen_fd_names) = get_listen_fds();
 
-    let child_pid =
-        run_container(id, &rootfs, &spec, -1, true, true, true, -1)?;
+    let child_pid = run_container(
+        id,
+        &rootfs,
+        &spec,
+        -1,
+        true,
+        true,
+        true,
+        false,
+        &mut RunOptions {
+            console_socket: &socket,
+            listen_fds: listen_fds,
+            listen_fd_names: &listen_fd_names,
+            preserve_fds: preserve_fds,
+            ..Default::default()
+        },
+    )?;
     if child_pid != -1 {
         debug!("writing init pid file {}", child_pid);
-        let mut f = File::create(INIT_PID)?;
-        f.write_all(child_pid.to_string().as_bytes())?;
+        write_atomic(INIT_PID, child_pid.to_string().as_bytes())?;
+        save_record(&oci::StateRecord {
+            pid: child_pid,
+            start_time: start_time(child_pid),
+            process_pid: -1,
+            process_start_time: 0,
+            created: created,
+            owner: getuid(),
+            bundle: bundle_path,
+            config_digest: config_digest,
+            listen_fds: listen_fds,
+            listen_fd_names: listen_fd_names,
+            preserve_fds: preserve_fds,
+        })?;
         if pidfile != "" {
             debug!("writing process {} pid to file {}", child_pid, pidfile);
-            let mut f = File::create(pidfile)?;
-            f.write_all(child_pid.to_string().as_bytes())?;
+            write_atomic(pidfile, child_pid.to_string().as_bytes())?;
         }
+        // NOTE: seccomp is kept so that processes started or executed
+        //       later are filtered the same way as the init process
+        let seccomp = spec.linux.as_mut().unwrap().seccomp.take();
+        // resources are kept so they can be updated later
+        let resources = spec.linux.as_mut().unwrap().resources.take();
         let linux = spec.linux.as_ref().unwrap();
         // update namespaces to enter only
         let mut namespaces = Vec::new();
@@ -552,11 +1226,11 @@ fn cmd_create(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
             uid_mappings: linux.uid_mappings.clone(),
             gid_mappings: linux.gid_mappings.clone(),
             sysctl: HashMap::new(),
-            resources: None,
+            resources: resources,
             cgroups_path: linux.cgroups_path.to_owned(),
             namespaces: namespaces,
             devices: Vec::new(),
-            seccomp: None,
+            seccomp: seccomp,
             rootfs_propagation: "".to_string(),
             masked_paths: Vec::new(),
             readonly_paths: Vec::new(),
@@ -579,15 +1253,14 @@ fn cmd_create(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
             windows: spec.windows,
         };
         debug!("writing updated config");
-        updated.save(CONFIG).chain_err(
-            || format!("failed to save {}", CONFIG),
-        )?;
+        save_atomic(&updated, CONFIG)?;
     }
     Ok(())
 }
 
 fn cmd_start(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
     debug!("Performing start");
+    let _lock = lock_instance(id, state_dir, false)?;
 
     // we use instance dir for config written out by create
     let dir = instance_dir(id, state_dir);
@@ -595,44 +1268,224 @@ fn cmd_start(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
         || format!("instance {} doesn't exist", id),
     )?;
 
-    let spec = Spec::load(CONFIG).chain_err(
-        || format!("failed to load {}", CONFIG),
-    )?;
+    let spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+
+    let console = format!("{}/console", dir);
+    let consolefd = match open(&*console, O_NOCTTY | O_RDWR, Mode::empty()) {
+        Err(e) => {
+            if e.errno() != Errno::ENOENT {
+                let msg = format!("failed to open {}", console);
+                return Err(e).chain_err(|| msg)?;
+            }
+            -1
+        }
+        Ok(fd) => fd,
+    };
+    let mut init = !matches.is_present("n");
+    let init_pid = get_init_pid()?;
+    let (listen_fds, listen_fd_names, preserve_fds) =
+        match oci::StateRecord::load(STATE) {
+            Ok(r) => (r.listen_fds, r.listen_fd_names, r.preserve_fds),
+            Err(_) => (0, String::new(), 0),
+        };
+    // NOTE: the stdio, listen fds and preserved fds given to create are
+    //       held by init, so we take them over for the container process.
+    //       With a terminal, the stdio of init is the pty it allocated.
+    let mut inherited = Vec::new();
+    if init_pid != -1 {
+        let mut targets = Vec::new();
+        if consolefd == -1 {
+            targets.extend(0..3);
+        }
+        targets.extend(3..3 + listen_fds + preserve_fds);
+        inherited = inherit_fds(init_pid, &targets)?;
+    }
+    if init_pid != -1 {
+        // NOTE: if init was set but we already have an init pid,
+        //       don't attempt to create another init.
+        init = false;
+    }
+
+
+    let child_pid = run_container(
+        id,
+        &spec.root.path,
+        &spec,
+        init_pid,
+        init,
+        false,
+        true,
+        false,
+        &mut RunOptions {
+            consolefd: consolefd,
+            inherited: &inherited,
+            listen_fds: listen_fds,
+            listen_fd_names: &listen_fd_names,
+            preserve_fds: preserve_fds,
+            ..Default::default()
+        },
+    )?;
+    if child_pid != -1 {
+        debug!("writing process {} pid file", child_pid);
+        write_atomic(PROCESS_PID, child_pid.to_string().as_bytes())?;
+        if let Ok(mut record) = oci::StateRecord::load(STATE) {
+            record.process_pid = child_pid;
+            record.process_start_time = start_time(child_pid);
+            save_record(&record)?;
+        }
+    }
+    Ok(())
+}
+
+fn cmd_events(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
+    debug!("Performing events");
+    let interval = parse_value::<u64>(matches, "interval")?.unwrap_or(5);
+    if interval == 0 {
+        let msg = "interval must be greater than zero".to_string();
+        return Err(ErrorKind::InvalidValue(msg).into());
+    }
+    let lock = lock_instance(id, state_dir, false)?;
+    chdir_instance(id, state_dir)?;
+    let st = state_from_dir(id, state_dir)?;
+    if st.status == "creating" || st.status == "stopped" {
+        bail!("container {} is not running", id);
+    }
+    let spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+    if matches.is_present("stats") {
+        return print_event(id, "stats", Some(cgroups::stats(&cpath)));
+    }
+    // NOTE: events runs until the container stops, so the lock is only
+    //       held while the state is read to avoid blocking delete
+    drop(lock);
+
+    let mut notifier = match cgroups::OomNotifier::new(&cpath) {
+        Ok(n) => Some(n),
+        Err(e) => {
+            warn!("oom events will not be reported: {}", e);
+            None
+        }
+    };
+    let period = Duration::from_secs(interval);
+    let mut next = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= next {
+            let st = {
+                let _lock = lock_instance(id, state_dir, false)?;
+                state_from_dir(id, state_dir)?
+            };
+            if st.status == "stopped" {
+                debug!("container {} stopped", id);
+                return Ok(());
+            }
+            print_event(id, "stats", Some(cgroups::stats(&cpath)))?;
+            next = now + period;
+            continue;
+        }
+        let wait = next - now;
+        let n = match notifier {
+            Some(ref mut n) => n,
+            None => {
+                sleep(wait);
+                continue;
+            }
+        };
+        // NOTE: poll takes an i32, so long intervals are waited for in
+        //       multiple steps
+        let millis = wait.as_secs()
+            .saturating_mul(1000)
+            .saturating_add(wait.subsec_nanos() as u64 / 1_000_000);
+        let timeout = cmp::min(millis, i32::max_value() as u64) as i32;
+        let pfds = &mut [PollFd::new(n.fd(), POLLIN, EventFlags::empty())];
+        match poll(pfds, timeout) {
+            Err(e) => {
+                if e.errno() != Errno::EINTR {
+                    return Err(e).chain_err(|| "unable to poll oom fd")?;
+                }
+            }
+            Ok(0) => {}
+            Ok(_) => {
+                if n.read()? {
+                    print_event(id, "oom", None)?;
+                }
+            }
+        }
+    }
+}
+
+fn print_event(id: &str, typ: &str, data: Option<oci::Stats>) -> Result<()> {
+    let event = oci::Event {
+        typ: typ.to_string(),
+        id: id.to_string(),
+        data: data,
+    };
+    println!("{}", event.to_string().chain_err(|| "invalid event")?);
+    Ok(())
+}
 
-    let console = format!("{}/console", dir);
-    let consolefd = match open(&*console, O_NOCTTY | O_RDWR, Mode::empty()) {
-        Err(e) => {
-            if e.errno() != Errno::ENOENT {
-                let msg = format!("failed to open {}", console);
-                return Err(e).chain_err(|| msg)?;
-            }
-            -1
+fn cmd_exec(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
+    debug!("Performing exec");
+    // load the process before changing to the instance dir so relative
+    // paths work as expected
+    let process = match matches.value_of("process") {
+        Some(path) => {
+            let p = oci::Process::load(path).chain_err(
+                || format!("failed to load {}", path),
+            )?;
+            Some(p)
         }
-        Ok(fd) => fd,
+        None => None,
     };
-    let mut init = !matches.is_present("n");
-    let init_pid = get_init_pid()?;
-    if init_pid != -1 {
-        // NOTE: if init was set but we already have an init pid,
-        //       don't attempt to create another init.
-        init = false;
+
+    let lock = lock_instance(id, state_dir, false)?;
+    chdir_instance(id, state_dir)?;
+    let st = state_from_dir(id, state_dir)?;
+    if st.status != "running" {
+        bail!("container {} is not running", id);
     }
 
+    // we use instance dir for config written out by create
+    let mut spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    if let Some(p) = process {
+        spec.process = p;
+    } else if let Some(args) = matches.values_of("args") {
+        spec.process.args = args.map(|s| s.to_string()).collect();
+    }
+    if spec.process.args.is_empty() {
+        let msg = "process args must not be empty".to_string();
+        return Err(ErrorKind::InvalidSpec(msg).into());
+    }
+    // hooks only run for the container process
+    spec.hooks = None;
 
     let child_pid = run_container(
         id,
         &spec.root.path,
         &spec,
-        init_pid,
-        init,
+        st.pid,
         false,
+        false,
+        matches.is_present("d"),
         true,
-        consolefd,
+        &mut RunOptions {
+            preserve_fds: get_preserve_fds(matches)?,
+            lock: Some(lock),
+            ..Default::default()
+        },
     )?;
     if child_pid != -1 {
-        debug!("writing process {} pid file", child_pid);
-        let mut f = File::create(PROCESS_PID)?;
-        f.write_all(child_pid.to_string().as_bytes())?;
+        let pidfile = matches.value_of("p").unwrap_or_default();
+        if pidfile != "" {
+            debug!("writing process {} pid to file {}", child_pid, pidfile);
+            write_atomic(pidfile, child_pid.to_string().as_bytes())?;
+        }
     }
     Ok(())
 }
@@ -641,15 +1494,25 @@ fn cmd_kill(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
     debug!("Performing kill");
     let signal = signals::to_signal(matches.value_of("signal").unwrap())
         .unwrap_or(Signal::SIGTERM);
+    let _lock = lock_instance(id, state_dir, false)?;
     let dir = instance_dir(id, state_dir);
     chdir(&*dir).chain_err(
         || format!("instance {} doesn't exist", id),
     )?;
+    if matches.is_present("a") {
+        let spec = Spec::load(CONFIG).chain_err(
+            || format!("failed to load {}", CONFIG),
+        )?;
+        let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+        return signal_all(&cpath, signal);
+    }
     let mut f = File::open(INIT_PID).chain_err(|| "failed to find pid")?;
     let mut result = String::new();
     f.read_to_string(&mut result)?;
+    let start_time =
+        oci::StateRecord::load(STATE).map(|r| r.start_time).unwrap_or(0);
     if let Ok(init_pid) = result.parse::<i32>() {
-        if signals::signal_process(init_pid, signal).is_err() {
+        if signals::signal_started(init_pid, start_time, signal).is_err() {
             warn!("failed signal init process {}, may have exited", init_pid);
         }
     } else {
@@ -658,44 +1521,380 @@ fn cmd_kill(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
-fn cmd_ps(id: &str, state_dir: &str) -> Result<()> {
+// NOTE: the cgroup is frozen while signaling so that nothing can fork
+//       its way out of the signal. If the freezer is missing or stuck,
+//       the processes are signaled anyway.
+fn signal_all(cpath: &str, signal: Signal) -> Result<()> {
+    let paused = cgroups::is_frozen(cpath);
+    let frozen = paused ||
+        match cgroups::freeze(cpath) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("failed to freeze {}, signaling anyway: {}", cpath, e);
+                false
+            }
+        };
+    // NOTE: a freeze that timed out may have left the cgroup freezing,
+    //       so we always try to thaw unless it was already paused
+    defer!(if !paused {
+        if let Err(e) = cgroups::thaw(cpath) {
+            if frozen {
+                warn!("failed to thaw {}: {}", cpath, e);
+            } else {
+                debug!("failed to thaw {}: {}", cpath, e);
+            }
+        }
+    });
+    for pid in cgroups::get_all_procs("cpuset", cpath) {
+        if let Err(e) = signals::signal_process(pid, signal) {
+            warn!("failed to signal {}: {}", pid, e);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_pause(id: &str, state_dir: &str) -> Result<()> {
+    debug!("Performing pause");
+    let _lock = lock_instance(id, state_dir, false)?;
+    chdir_instance(id, state_dir)?;
+    let st = state_from_dir(id, state_dir)?;
+    if st.status != "running" {
+        bail!("container {} is not running", id);
+    }
+    let spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+    cgroups::freeze(&cpath).chain_err(
+        || format!("failed to pause container {}", id),
+    )?;
+    Ok(())
+}
+
+fn cmd_resume(id: &str, state_dir: &str) -> Result<()> {
+    debug!("Performing resume");
+    let _lock = lock_instance(id, state_dir, false)?;
+    chdir_instance(id, state_dir)?;
+    let st = state_from_dir(id, state_dir)?;
+    if st.status != "paused" {
+        bail!("container {} is not paused", id);
+    }
+    let spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+    cgroups::thaw(&cpath).chain_err(
+        || format!("failed to resume container {}", id),
+    )?;
+    Ok(())
+}
+
+fn cmd_update(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
+    debug!("Performing update");
+    // load resources before changing to the instance dir so relative
+    // paths work as expected
+    let new = match matches.value_of("resources") {
+        Some(path) => {
+            let r = LinuxResources::load(path).chain_err(
+                || format!("failed to load {}", path),
+            )?;
+            Some(r)
+        }
+        None => None,
+    };
+
+    let _lock = lock_instance(id, state_dir, false)?;
+    chdir_instance(id, state_dir)?;
+    let st = state_from_dir(id, state_dir)?;
+    if st.status == "creating" || st.status == "stopped" {
+        bail!("cannot update {} container {}", st.status, id);
+    }
+    let mut spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    {
+        let linux = spec.linux.as_mut().unwrap();
+        let mut resources = linux.resources.take().unwrap_or_default();
+        if let Some(r) = new {
+            merge_resources(&mut resources, r);
+        }
+        resources_from_flags(&mut resources, matches)?;
+        let cpath = cgroups_path(id, linux);
+        cgroups::update(&resources, &cpath).chain_err(
+            || format!("failed to update container {}", id),
+        )?;
+        linux.resources = Some(resources);
+    }
+    debug!("writing updated config");
+    save_atomic(&spec, CONFIG)?;
+    Ok(())
+}
+
+// replaces values in r with any values that are set in new
+fn merge_resources(r: &mut LinuxResources, new: LinuxResources) {
+    macro_rules! merge {
+        ($dst:expr, $src:expr, $($field:ident),*) => {{
+            $(if $src.$field.is_some() { $dst.$field = $src.$field; })*
+        }}
+    }
+    // NOTE: update only changes limits, the rest is fixed at create
+    if !new.devices.is_empty() || new.disable_oom_killer ||
+        new.oom_score_adj.is_some() || new.network.is_some() ||
+        !new.hugepage_limits.is_empty()
+    {
+        warn!("only memory, cpu, pids and block io limits can be updated");
+    }
+    merge!(r, new, pids);
+    if let Some(memory) = new.memory {
+        let m = r.memory.get_or_insert_with(Default::default);
+        merge!(m, memory, limit, reservation, swap, kernel, kernel_tcp);
+        merge!(m, memory, swappiness);
+    }
+    if let Some(cpu) = new.cpu {
+        let c = r.cpu.get_or_insert_with(Default::default);
+        merge!(c, cpu, shares, quota, period);
+        merge!(c, cpu, realtime_runtime, realtime_period);
+        if !cpu.cpus.is_empty() {
+            c.cpus = cpu.cpus;
+        }
+        if !cpu.mems.is_empty() {
+            c.mems = cpu.mems;
+        }
+    }
+    if let Some(blkio) = new.block_io {
+        let b = r.block_io.get_or_insert_with(Default::default);
+        merge!(b, blkio, weight, leaf_weight);
+        if !blkio.weight_device.is_empty() {
+            b.weight_device = blkio.weight_device;
+        }
+        if !blkio.throttle_read_bps_device.is_empty() {
+            b.throttle_read_bps_device = blkio.throttle_read_bps_device;
+        }
+        if !blkio.throttle_write_bps_device.is_empty() {
+            b.throttle_write_bps_device = blkio.throttle_write_bps_device;
+        }
+        if !blkio.throttle_read_iops_device.is_empty() {
+            b.throttle_read_iops_device = blkio.throttle_read_iops_device;
+        }
+        if !blkio.throttle_write_iops_device.is_empty() {
+            b.throttle_write_iops_device = blkio.throttle_write_iops_device;
+        }
+    }
+    r.unified.extend(new.unified);
+}
+
+fn resources_from_flags(
+    r: &mut LinuxResources,
+    matches: &ArgMatches,
+) -> Result<()> {
+    if let Some(v) = parse_value(matches, "memory")? {
+        r.memory.get_or_insert_with(Default::default).limit = Some(v);
+    }
+    if let Some(v) = parse_value(matches, "memory-reservation")? {
+        r.memory.get_or_insert_with(Default::default).reservation = Some(v);
+    }
+    if let Some(v) = parse_value(matches, "memory-swap")? {
+        r.memory.get_or_insert_with(Default::default).swap = Some(v);
+    }
+    if let Some(v) = parse_value(matches, "cpu-shares")? {
+        r.cpu.get_or_insert_with(Default::default).shares = Some(v);
+    }
+    if let Some(v) = parse_value(matches, "cpu-quota")? {
+        r.cpu.get_or_insert_with(Default::default).quota = Some(v);
+    }
+    if let Some(v) = parse_value(matches, "cpu-period")? {
+        r.cpu.get_or_insert_with(Default::default).period = Some(v);
+    }
+    if let Some(v) = matches.value_of("cpuset-cpus") {
+        r.cpu.get_or_insert_with(Default::default).cpus = v.to_string();
+    }
+    if let Some(v) = matches.value_of("cpuset-mems") {
+        r.cpu.get_or_insert_with(Default::default).mems = v.to_string();
+    }
+    if let Some(v) = parse_value(matches, "pids-limit")? {
+        r.pids = Some(oci::LinuxPids { limit: v });
+    }
+    if let Some(v) = parse_value(matches, "blkio-weight")? {
+        r.block_io.get_or_insert_with(Default::default).weight = Some(v);
+    }
+    Ok(())
+}
+
+// NOTE: systemd passes socket activated fds starting at fd 3. They keep
+//       their numbers through the forks into the container process and
+//       any preserved fds come after them.
+fn get_listen_fds() -> (i32, String) {
+    let var =
+        |k: &str| std::env::var(k).ok().and_then(|v| v.parse::<i32>().ok());
+    if var("LISTEN_PID") != Some(getpid()) {
+        return (0, String::new());
+    }
+    match var("LISTEN_FDS") {
+        Some(n) if n > 0 => {
+            (n, std::env::var("LISTEN_FDNAMES").unwrap_or_default())
+        }
+        _ => (0, String::new()),
+    }
+}
+
+// sets the socket activation vars for the pid inside the container
+fn listen_env(env: &[String], listen_fds: i32, names: &str) -> Vec<String> {
+    let listen = ["LISTEN_PID=", "LISTEN_FDS=", "LISTEN_FDNAMES="];
+    let mut result: Vec<String> = env.iter()
+        .filter(|e| !listen.iter().any(|l| e.starts_with(l)))
+        .cloned()
+        .collect();
+    result.push(format!("LISTEN_PID={}", getpid()));
+    result.push(format!("LISTEN_FDS={}", listen_fds));
+    if !names.is_empty() {
+        result.push(format!("LISTEN_FDNAMES={}", names));
+    }
+    result
+}
+
+#[inline]
+fn get_preserve_fds(matches: &ArgMatches) -> Result<i32> {
+    Ok(parse_value::<u16>(matches, "preserve-fds")?.unwrap_or(0) as i32)
+}
+
+fn parse_value<T: FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+) -> Result<Option<T>> {
+    match matches.value_of(name) {
+        Some(v) => {
+            if let Ok(x) = v.parse::<T>() {
+                Ok(Some(x))
+            } else {
+                let msg = format!("{} is not a valid value for {}", v, name);
+                Err(ErrorKind::InvalidValue(msg).into())
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+fn cmd_ps(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
     debug!("Performing ps");
     let dir = instance_dir(id, state_dir);
     chdir(&*dir).chain_err(
         || format!("instance {} doesn't exist", id),
     )?;
-    let mut f = File::open(PROCESS_PID).chain_err(|| "failed to find pid")?;
-    let mut result = String::new();
-    f.read_to_string(&mut result)?;
-    // TODO: return any other execed processes
-    let mut pids = Vec::new();
-    if let Ok(process_pid) = result.parse::<i32>() {
-        pids.push(process_pid);
-    } else {
-        warn!("invalid process pid: {}", result);
+    let spec = Spec::load(CONFIG).chain_err(
+        || format!("failed to load {}", CONFIG),
+    )?;
+    let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+    let mut pids = cgroups::get_all_procs("cpuset", &cpath);
+    pids.sort();
+    if matches.value_of("f") != Some("table") {
+        println!(
+            "{}",
+            oci::serialize::to_string(&pids).chain_err(
+                || "could not serialize pids",
+            )?
+        );
+        return Ok(());
+    }
+    let mut rows = vec![
+        vec![
+            "USER".to_string(),
+            "PID".to_string(),
+            "PPID".to_string(),
+            "STATE".to_string(),
+            "START".to_string(),
+            "COMMAND".to_string(),
+        ],
+    ];
+    for pid in pids {
+        // NOTE: the process may exit while we are looking at it
+        let (stat, uid, cmd) =
+            match (procfs::stat(pid), procfs::uid(pid), procfs::cmdline(pid)) {
+                (Ok(stat), Ok(uid), Ok(cmd)) => (stat, uid, cmd),
+                _ => continue,
+            };
+        let user = match user_name(uid) {
+            Some(name) => name,
+            None => uid.to_string(),
+        };
+        rows.push(vec![
+            user,
+            pid.to_string(),
+            stat.ppid.to_string(),
+            stat.state.to_string(),
+            logger::format_time(procfs::start_time(stat.start_time)?),
+            cmd,
+        ]);
     }
-    println!(
-        "{}",
-        oci::serialize::to_string(&pids).chain_err(
-            || "could not serialize pids",
-        )?
-    );
+    print_table(&rows);
     Ok(())
 }
 
-fn cmd_delete(id: &str, state_dir: &str) -> Result<()> {
+// kills every process in the cgroup and waits for them to exit. The
+// cgroup of a live container must have processes in it, otherwise they
+// would escape the kill.
+fn kill_all(id: &str, cpath: &str, live: bool) -> Result<()> {
+    debug!("killing all processes in {}", cpath);
+    let start = Instant::now();
+    let mut first = true;
+    loop {
+        let pids = match cgroups::read_all_procs("cpuset", cpath) {
+            Ok(pids) => pids,
+            // NOTE: the cgroup may be removed once it is empty
+            Err(Error(ErrorKind::Io(ref e), _))
+                if e.kind() == std::io::ErrorKind::NotFound &&
+                    !(live && first) => Vec::new(),
+            Err(e) => {
+                // NOTE: rootless containers may not have a cgroup
+                if is_rootless() {
+                    warn!("could not kill processes in {}: {}", cpath, e);
+                    return Ok(());
+                }
+                let msg = format!("could not find processes of {}", id);
+                return Err(e).chain_err(|| msg);
+            }
+        };
+        if pids.is_empty() {
+            if live && first && !is_rootless() {
+                bail!("no processes found in cgroup of container {}", id);
+            }
+            return Ok(());
+        }
+        first = false;
+        if start.elapsed() > Duration::from_millis(KILL_TIMEOUT) {
+            bail!("processes in container {} did not exit", id);
+        }
+        // NOTE: keep signaling in case anything forked in the meantime
+        for pid in pids {
+            if let Err(e) = signals::signal_process(pid, Signal::SIGKILL) {
+                debug!("failed to kill {}: {}", pid, e);
+            }
+        }
+        // frozen processes won't exit until they are thawed
+        if cgroups::is_frozen(cpath) {
+            cgroups::thaw(cpath)?;
+        }
+        sleep(Duration::from_millis(KILL_INTERVAL));
+    }
+}
+
+fn cmd_delete(id: &str, state_dir: &str, matches: &ArgMatches) -> Result<()> {
     debug!("Performing delete");
+    let lock = lock_instance(id, state_dir, false)?;
     let dir = instance_dir(id, state_dir);
     if chdir(&*dir).is_err() {
         debug!("instance {} doesn't exist", id);
         warn!("returning zero to work around docker bug");
         return Ok(());
     }
+    let force = matches.is_present("f");
     if let Ok(mut f) = File::open(PROCESS_PID) {
         let mut result = String::new();
         f.read_to_string(&mut result)?;
         if let Ok(process_pid) = result.parse::<i32>() {
-            if signals::signal_process(process_pid, None).is_ok() {
+            let start_time = oci::StateRecord::load(STATE)
+                .map(|r| r.process_start_time)
+                .unwrap_or(0);
+            if process_alive(process_pid, start_time) && !force {
                 bail!("container process {} is still running", process_pid);
             }
         } else {
@@ -704,12 +1903,27 @@ fn cmd_delete(id: &str, state_dir: &str) -> Result<()> {
     } else {
         debug!("process doesn't exist");
     }
+    // NOTE: this also kills executed processes and anything that outlived
+    //       the container process
+    if force {
+        if let Ok(spec) = Spec::load(CONFIG) {
+            let live = match state_from_dir(id, state_dir) {
+                Ok(st) => st.status != "creating" && st.status != "stopped",
+                Err(_) => false,
+            };
+            let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
+            kill_all(id, &cpath, live)?;
+        }
+    }
     if let Ok(mut f) = File::open(INIT_PID) {
         debug!("killing init process");
         let mut result = String::new();
         f.read_to_string(&mut result)?;
+        let start_time =
+            oci::StateRecord::load(STATE).map(|r| r.start_time).unwrap_or(0);
         if let Ok(ipid) = result.parse::<i32>() {
-            if let Err(e) = signals::signal_process(ipid, Signal::SIGKILL) {
+            let sig = Signal::SIGKILL;
+            if let Err(e) = signals::signal_started(ipid, start_time, sig) {
                 let chain = || format!("failed to kill init {} ", ipid);
                 if let Error(ErrorKind::Nix(n), _) = e {
                     if n.errno() == Errno::ESRCH {
@@ -728,12 +1942,7 @@ fn cmd_delete(id: &str, state_dir: &str) -> Result<()> {
         debug!("init process doesn't exist");
     }
     if let Ok(spec) = Spec::load(CONFIG) {
-        let linux = spec.linux.as_ref().unwrap();
-        let cpath = if linux.cgroups_path == "" {
-            format!{"/{}", id}
-        } else {
-            linux.cgroups_path.clone()
-        };
+        let cpath = cgroups_path(id, spec.linux.as_ref().unwrap());
         debug!("removing cgroups");
         if let Err(Error(ErrorKind::Io(e), _)) = cgroups::remove(&cpath) {
             if e.kind() != std::io::ErrorKind::NotFound {
@@ -760,18 +1969,22 @@ fn cmd_delete(id: &str, state_dir: &str) -> Result<()> {
         }
         bail!("State dir for {} disappeared", id);
     }
+    lock.remove()?;
 
     Ok(())
 }
 
-fn cmd_run(id: &str, matches: &ArgMatches) -> Result<()> {
+fn cmd_run(id: &str, matches: &ArgMatches, systemd_cgroup: bool) -> Result<()> {
     let bundle = matches.value_of("bundle").unwrap();
     chdir(&*bundle).chain_err(
         || format!("failed to chdir to {}", bundle),
     )?;
-    let spec = Spec::load(CONFIG).chain_err(
+    let mut spec = Spec::load(CONFIG).chain_err(
         || format!("failed to load {}", CONFIG),
     )?;
+    check_cgroups_path(id, &mut spec, systemd_cgroup)?;
+    let socket = console_socket_path(matches)?;
+    let (listen_fds, listen_fd_names) = get_listen_fds();
 
     let child_pid = run_container(
         id,
@@ -781,7 +1994,14 @@ fn cmd_run(id: &str, matches: &ArgMatches) -> Result<()> {
         !matches.is_present("n"),
         matches.is_present("o"),
         matches.is_present("d"),
-        -1,
+        false,
+        &mut RunOptions {
+            console_socket: &socket,
+            listen_fds: listen_fds,
+            listen_fd_names: &listen_fd_names,
+            preserve_fds: get_preserve_fds(matches)?,
+            ..Default::default()
+        },
     )?;
     info!("Container running with pid {}", child_pid);
     Ok(())
@@ -847,6 +2067,35 @@ fn execute_hook(hook: &oci::Hook, state: &oci::State) -> Result<()> {
     Ok(())
 }
 
+// what the container process gets handed besides the spec
+struct RunOptions<'a> {
+    // console given to create, or -1
+    consolefd: RawFd,
+    // socket to send the pty master to
+    console_socket: &'a str,
+    // fds taken over from init, with the fd to install them at
+    inherited: &'a [(RawFd, RawFd)],
+    listen_fds: i32,
+    listen_fd_names: &'a str,
+    preserve_fds: i32,
+    // released once the process is in the container
+    lock: Option<Lock>,
+}
+
+impl<'a> Default for RunOptions<'a> {
+    fn default() -> RunOptions<'a> {
+        RunOptions {
+            consolefd: -1,
+            console_socket: "",
+            inherited: &[],
+            listen_fds: 0,
+            listen_fd_names: "",
+            preserve_fds: 0,
+            lock: None,
+        }
+    }
+}
+
 fn run_container(
     id: &str,
     rootfs: &str,
@@ -855,7 +2104,8 @@ fn run_container(
     mut init: bool,
     mut init_only: bool,
     daemonize: bool,
-    consolefd: RawFd,
+    exec: bool,
+    opts: &mut RunOptions,
 ) -> Result<(i32)> {
     if let Err(e) = prctl::set_dumpable(false) {
         bail!(format!("set dumpable returned {}", e));
@@ -879,6 +2129,11 @@ fn run_container(
 
     let linux = spec.linux.as_ref().unwrap();
 
+    if !opts.console_socket.is_empty() && !spec.process.terminal {
+        let msg = "console socket requires process.terminal".to_string();
+        return Err(ErrorKind::InvalidSpec(msg).into());
+    }
+
     // initialize static variables before forking
     initialize(&DEFAULT_DEVICES);
     initialize(&NAMESPACES);
@@ -910,11 +2165,7 @@ fn run_container(
     }
 
 
-    let cpath = if linux.cgroups_path == "" {
-        format!{"/{}", id}
-    } else {
-        linux.cgroups_path.clone()
-    };
+    let cpath = cgroups_path(id, linux);
 
     let mut bind_devices = false;
     let mut userns = false;
@@ -924,23 +2175,45 @@ fn run_container(
         bind_devices = true;
         userns = true;
     }
+    // NOTE: we are root inside the user namespace, so this has to be
+    //       checked before we fork
+    let rootless = is_rootless();
+    let enter_userns = to_enter.iter().any(|&(s, _)| s == CLONE_NEWUSER);
+    if rootless && !userns && !enter_userns {
+        let msg = "rootless containers require a user namespace".to_string();
+        return Err(ErrorKind::InvalidSpec(msg).into());
+    }
 
     if !daemonize {
         if let Err(e) = prctl::set_child_subreaper(true) {
             bail!(format!("set subreaper returned {}", e));
         };
     }
-    let (child_pid, wfd) = fork_first(
+    // the pty is created inside the container, so we need a way to get
+    // the master back out to the first parent. Start uses the pty that
+    // init allocated during create.
+    let start = init_pid != -1 && !exec;
+    let console = if spec.process.terminal && opts.consolefd == -1 && !start {
+        Some(FdSocket::new().chain_err(|| "failed to create socket")?)
+    } else {
+        None
+    };
+    let pidsock = PidSocket::new().chain_err(|| "failed to create socket")?;
+    let (child_pid, mut wfd) = fork_first(
         id,
         init_pid,
         enter_pid,
         init_only,
         daemonize,
+        exec,
         userns,
         linux,
         rlimits,
         &cpath,
         spec,
+        &console,
+        &pidsock,
+        opts,
     )?;
 
     // parent returns child pid and exits
@@ -966,7 +2239,6 @@ fn run_container(
         }
     }
 
-    // TODO: handle systemd-style cgroup_path
     if !cpath.starts_with('/') {
         let msg = "cgroup path must be absolute".to_string();
         return Err(ErrorKind::InvalidSpec(msg).into());
@@ -981,41 +2253,29 @@ fn run_container(
         fork_enter_pid(init, daemonize)?;
     };
 
+    // let the first parent know which process ended up in the container
+    pidsock.send().chain_err(|| "failed to send pid")?;
+
     if cf.contains(CLONE_NEWUTS) {
         sethostname(&spec.hostname)?;
     }
 
-    // NOTE: if we are running without a supplied console, then
-    //       stdout and stderr will not be properly passed to
-    //       docker since the start command has different stdout
-    //       than the init command. In order to make this work
-    //       we would need init to make a pseudoterminal and copy
-    //       data back and forth, or pass the stdio file discriptors
-    //       over a socket of some sort.
-    if consolefd != -1 {
-        setsid()?;
-        if unsafe { libc::ioctl(consolefd, libc::TIOCSCTTY) } < 0 {
-            warn!("could not TIOCSCTTY");
-        };
-        dup2(consolefd, 0).chain_err(
-            || "could not dup tty to stdin",
-        )?;
-        dup2(consolefd, 1).chain_err(
-            || "could not dup tty to stdout",
-        )?;
-        dup2(consolefd, 2).chain_err(
-            || "could not dup tty to stderr",
-        )?;
-
-    // NOTE: we may need to fix up the mount of /dev/console
-    } else if daemonize && !init_only {
-        close(0).chain_err(|| "could not close stdin")?;
-        close(1).chain_err(|| "could not close stdout")?;
-        close(2).chain_err(|| "could not close stderr")?;
+    // NOTE: without a supplied console or a terminal, the process keeps
+    //       our stdio even when daemonized. Start passes in the stdio
+    //       that was given to create instead, which is the pty that init
+    //       allocated if there is a terminal.
+    if opts.consolefd != -1 {
+        tty::set_controlling(opts.consolefd)?;
+    }
+    let (stdio, extra): (Vec<_>, Vec<_>) =
+        opts.inherited.iter().partition(|&&(target, _)| target < 3);
+    install_fds(&stdio)?;
+    if start && spec.process.terminal && opts.consolefd == -1 {
+        tty::set_controlling(0)?;
     }
 
     if cf.contains(CLONE_NEWNS) {
-        mounts::init_rootfs(spec, rootfs, &cpath, bind_devices)
+        mounts::init_rootfs(spec, rootfs, &cpath, bind_devices, rootless)
             .chain_err(|| "failed to init rootfs")?;
     }
 
@@ -1041,7 +2301,12 @@ fn run_container(
 
         // only set sysctls in newns
         for (key, value) in &linux.sysctl {
-            set_sysctl(key, value)?;
+            if let Err(e) = set_sysctl(key, value) {
+                if !rootless {
+                    return Err(e);
+                }
+                warn!("skipping sysctl {} in rootless container: {}", key, e);
+            }
         }
 
         // NOTE: apparently criu has problems if pointing to an fd outside
@@ -1054,17 +2319,42 @@ fn run_container(
         )?;
     }
 
+    // NOTE: the pty must be opened after we are in the mount namespace
+    //       so that it is allocated from the container's devpts
+    if let Some(ref console) = console {
+        let master = setup_tty(spec, !exec, !init_only)?;
+        console.send(master).chain_err(|| "failed to send pty master")?;
+        close(master)?;
+    }
+
     // change to specified working directory
     if !spec.process.cwd.is_empty() {
         chdir(&*spec.process.cwd)?;
     }
 
+    // NOTE: the fds we still need may have the numbers that the fds
+    //       from start are installed at, so they are moved out of the way
+    let min = 3 + opts.listen_fds + opts.preserve_fds;
+    if !extra.is_empty() {
+        wfd = move_fd(wfd, min)?;
+        logger::move_file(min).chain_err(|| "failed to move log file")?;
+        install_fds(&extra)?;
+    }
+
+    // NOTE: this has to happen before seccomp, which may block it
+    set_cloexec_from(min)?;
+
     debug!("setting ids");
 
     // set uid/gid/groups
     setid(spec.process.user.uid, spec.process.user.gid)?;
     if !spec.process.user.additional_gids.is_empty() {
-        setgroups(&spec.process.user.additional_gids)?;
+        // NOTE: setgroups is denied before the gid map is written
+        if rootless && userns {
+            warn!("ignoring additional gids in rootless container");
+        } else {
+            setgroups(&spec.process.user.additional_gids)?;
+        }
     }
 
     // NOTE: if we want init to pass signals to other processes, we may want
@@ -1101,7 +2391,12 @@ fn run_container(
     }
     // we nolonger need wfd, so close it
     close(wfd).chain_err(|| "could not close wfd")?;
-    do_exec(&spec.process.args[0], &spec.process.args, &spec.process.env)?;
+    let env = if opts.listen_fds > 0 {
+        listen_env(&spec.process.env, opts.listen_fds, opts.listen_fd_names)
+    } else {
+        spec.process.env.clone()
+    };
+    do_exec(&spec.process.args[0], &spec.process.args, &env)?;
     Ok(-1)
 }
 
@@ -1111,11 +2406,15 @@ fn fork_first(
     enter_pid: bool,
     init_only: bool,
     daemonize: bool,
+    exec: bool,
     userns: bool,
     linux: &Linux,
     rlimits: &[LinuxRlimit],
     cpath: &str,
     spec: &Spec,
+    console: &Option<FdSocket>,
+    pidsock: &PidSocket,
+    opts: &mut RunOptions,
 ) -> Result<(i32, RawFd)> {
     let ccond = Cond::new().chain_err(|| "failed to create cond")?;
     let pcond = Cond::new().chain_err(|| "failed to create cond")?;
@@ -1159,14 +2458,35 @@ fn fork_first(
                     &format!("/proc/{}/uid_map", child),
                     &linux.uid_mappings,
                 ).chain_err(|| "failed to write uid mappings")?;
+                // an unprivileged user can only write the gid map once
+                // setgroups is disabled
+                if is_rootless() {
+                    write_setgroups(child, "deny").chain_err(
+                        || "failed to disable setgroups",
+                    )?;
+                }
                 write_mappings(
                     &format!("/proc/{}/gid_map", child),
                     &linux.gid_mappings,
                 ).chain_err(|| "failed to write gid mappings")?;
             }
             // setup cgroups
+            // NOTE: create has already applied the resources, so the
+            //       process that start runs only has to join them
             let schild = child.to_string();
-            cgroups::apply(&linux.resources, &schild, cpath)?;
+            let result = if exec || init_pid != -1 {
+                cgroups::join(&schild, cpath)
+            } else {
+                apply_cgroups(linux, child, cpath)
+            };
+            // NOTE: rootless containers can only use cgroups that have
+            //       been delegated to the user, so they are best effort
+            if let Err(e) = result {
+                if !is_rootless() {
+                    return Err(e);
+                }
+                warn!("skipping cgroups in rootless container: {}", e);
+            }
             // notify child
             pcond.notify().chain_err(|| "failed to notify child")?;
 
@@ -1175,21 +2495,17 @@ fn fork_first(
             if enter_pid {
                 let (_, _) = wait_for_child(child)?;
             }
-            let mut pid = -1;
+            let pid = pidsock.recv().chain_err(|| "failed to receive pid")?;
+            debug!("actual pid of child is {}", pid);
+            // NOTE: the process is in the container now, so the instance
+            //       doesn't need to stay locked while we wait for it
+            drop(opts.lock.take());
             wait_for_pipe_zero(rfd, -1)?;
-            // get the actual pid of the process from cgroup
-            let procs = cgroups::get_procs("cpuset", cpath);
-            for p in procs {
-                if p != init_pid {
-                    debug!("actual pid of child is {}", p);
-                    pid = p;
-                    break;
-                }
-            }
             if !init_only {
                 debug!("running prestart hooks");
+                let bundle = bundle_dir()?;
                 if let Some(ref hooks) = spec.hooks {
-                    let st = state(id, "running", init_pid, &spec.root.path);
+                    let st = state(id, "running", init_pid, &bundle);
                     for h in &hooks.prestart {
                         execute_hook(h, &st).chain_err(
                             || "failed to execute prestart hooks",
@@ -1199,7 +2515,7 @@ fn fork_first(
                 wait_for_pipe_zero(rfd, -1)?;
                 debug!("running poststart hooks");
                 if let Some(ref hooks) = spec.hooks {
-                    let st = state(id, "running", init_pid, &spec.root.path);
+                    let st = state(id, "running", init_pid, &bundle);
                     for h in &hooks.poststart {
                         if let Err(e) = execute_hook(h, &st) {
                             warn!("failed to execute poststart hook: {}", e);
@@ -1207,20 +2523,66 @@ fn fork_first(
                     }
                 }
             }
+            let master = match *console {
+                Some(ref c) => {
+                    c.recv().chain_err(|| "failed to receive pty master")?
+                }
+                None => -1,
+            };
+            if master != -1 && !opts.console_socket.is_empty() {
+                send_console(opts.console_socket, master)?;
+                close(master)?;
+            } else if daemonize && master != -1 {
+                warn!("no console socket to pass the terminal to");
+                close(master)?;
+            }
             if daemonize {
                 debug!("first parent exiting for daemonization");
                 return Ok((pid, wfd));
             }
             signals::pass_signals(pid)?;
+            if master != -1 && opts.console_socket.is_empty() {
+                let size = &spec.process.console_size;
+                tty::proxy(master, size.height == 0 && size.width == 0)?;
+            }
             let sig = wait_for_pipe_sig(rfd, -1)?;
-            let (exit_code, _) = wait_for_child(pid)?;
-            cgroups::remove(cpath)?;
+            let (exit_code, _) = wait_for_exit(pid)?;
+            // the cgroup belongs to the container, not the executed process
+            if !exec {
+                if let Err(e) = cgroups::remove(cpath) {
+                    if !is_rootless() {
+                        return Err(e);
+                    }
+                    debug!("failed to remove cgroup in rootless mode: {}", e);
+                }
+            }
             exit(exit_code, sig)?;
         }
     };
     Ok((-1, wfd))
 }
 
+// NOTE: the console socket is connected to after changing directories,
+//       so a relative path is resolved up front
+fn console_socket_path(matches: &ArgMatches) -> Result<String> {
+    let path = match matches.value_of("console-socket") {
+        Some(path) => path,
+        None => return Ok(String::new()),
+    };
+    let abs = canonicalize(path)
+        .chain_err(|| format!("failed to find console socket {}", path))?;
+    Ok(abs.to_string_lossy().into_owned())
+}
+
+fn send_console(path: &str, master: RawFd) -> Result<()> {
+    debug!("sending pty master to {}", path);
+    let stream = UnixStream::connect(path)
+        .chain_err(|| format!("failed to connect to {}", path))?;
+    send_fd(stream.as_raw_fd(), master)
+        .chain_err(|| format!("failed to send pty master to {}", path))?;
+    Ok(())
+}
+
 fn fork_enter_pid(init: bool, daemonize: bool) -> Result<()> {
     // do the first fork right away because we must fork before we can
     // mount proc. The child will be in the pid namespace.
@@ -1305,6 +2667,60 @@ fn do_init(wfd: RawFd, daemonize: bool) -> Result<()> {
     }
 }
 
+// opens a pty and makes the slave the controlling terminal, returning
+// the master. The console is only mounted for the main process.
+// NOTE: init only holds the slave as its stdio, so that start can make
+//       it the controlling terminal of the container process
+fn setup_tty(spec: &Spec, mount_console: bool, controlling: bool)
+    -> Result<RawFd> {
+    let (master, slave, name) = tty::open_pty()?;
+    let size = &spec.process.console_size;
+    if size.height != 0 || size.width != 0 {
+        tty::set_size(slave, size.height, size.width)?;
+    }
+    if mount_console {
+        tty::mount_console(&name)?;
+    }
+    if controlling {
+        tty::set_controlling(slave)?;
+    } else {
+        tty::set_stdio(slave)?;
+    }
+    close(slave)?;
+    Ok(master)
+}
+
+// marks every fd from first on close on exec so nothing railcar inherited
+// leaks into the container process
+fn set_cloexec_from(first: RawFd) -> Result<()> {
+    match close_range(first as u32, !0, CLOSE_RANGE_CLOEXEC) {
+        Ok(()) => return Ok(()),
+        // CLOSE_RANGE_CLOEXEC was added after close_range itself
+        Err(nix::Error::Sys(Errno::ENOSYS)) |
+        Err(nix::Error::Sys(Errno::EINVAL)) => {}
+        Err(e) => return Err(e).chain_err(|| "failed to close_range"),
+    }
+    // collect the fds first since reading the dir uses an fd
+    let mut fds = Vec::new();
+    for entry in read_dir("/proc/self/fd")? {
+        let name = entry?.file_name();
+        if let Ok(fd) = name.to_string_lossy().parse::<RawFd>() {
+            if fd >= first {
+                fds.push(fd);
+            }
+        }
+    }
+    for fd in fds {
+        // the fd used to read the dir is already closed
+        if let Err(e) = fcntl(fd, FcntlArg::F_SETFD(FD_CLOEXEC)) {
+            if e.errno() != Errno::EBADF {
+                return Err(e).chain_err(|| format!("failed to set {}", fd));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn do_exec(path: &str, args: &[String], env: &[String]) -> Result<()> {
     let p = CString::new(path.to_string()).unwrap();
     let a: Vec<CString> = args.iter()
@@ -1324,6 +2740,21 @@ fn do_exec(path: &str, args: &[String], env: &[String]) -> Result<()> {
     Ok(())
 }
 
+fn apply_cgroups(linux: &Linux, pid: i32, cpath: &str) -> Result<()> {
+    if systemd::is_systemd_path(&linux.cgroups_path) {
+        systemd::start_unit(&linux.cgroups_path, pid, &linux.resources)?;
+    }
+    cgroups::apply(&linux.resources, &pid.to_string(), cpath)
+}
+
+fn write_setgroups(pid: i32, value: &str) -> Result<()> {
+    let path = format!("/proc/{}/setgroups", pid);
+    let fd = open(&*path, O_WRONLY, Mode::empty())?;
+    defer!(close(fd).unwrap());
+    write(fd, value.as_bytes())?;
+    Ok(())
+}
+
 fn write_mappings(path: &str, maps: &[LinuxIDMapping]) -> Result<()> {
     let mut data = String::new();
     for m in maps {
@@ -1449,6 +2880,27 @@ fn wait_for_pipe_zero(rfd: RawFd, timeout: i32) -> Result<()> {
     Ok(())
 }
 
+// NOTE: if the kernel supports pidfds, we poll for the exit of the
+//       process instead of being woken up by every child that exits
+fn wait_for_exit(pid: i32) -> Result<(i8, Option<Signal>)> {
+    if let Ok(Some(pidfd)) = signals::open_pidfd(pid) {
+        defer!({ let _ = close(pidfd); });
+        loop {
+            let mut pfds = [PollFd::new(pidfd, POLLIN, EventFlags::empty())];
+            match poll(&mut pfds, -1) {
+                Ok(_) => break,
+                Err(e) => {
+                    if e.errno() != Errno::EINTR {
+                        let msg = format!("could not poll pidfd of {}", pid);
+                        return Err(e).chain_err(|| msg)?;
+                    }
+                }
+            }
+        }
+    }
+    wait_for_child(pid)
+}
+
 fn wait_for_child(child: i32) -> Result<(i8, Option<Signal>)> {
     loop {
         // wait on all children, but only return if we match child.
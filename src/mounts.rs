@@ -1,17 +1,21 @@
 use cgroups;
 use errors::*;
+use libc;
 use nix::{Errno, NixPath};
-use nix::fcntl::{open, O_DIRECTORY, O_RDWR, O_RDONLY, O_CREAT};
+use nix::fcntl::{open, O_DIRECTORY, O_RDONLY};
 use nix::mount::*;
-use nix::sys::stat::{mknod, umask};
+use nix::sys::stat::umask;
 use nix::sys::stat::{Mode, SFlag, S_IFBLK, S_IFCHR, S_IFIFO};
-use nix::unistd::{close, getcwd, chdir, pivot_root, chown};
-use nix_ext::fchdir;
+use nix::unistd::{close, pivot_root};
+use nix_ext::{fchdir, fchownat, mknodat, symlinkat, unlinkat};
+use nix_ext::mount_setattr;
 use oci::{Mount, Spec, LinuxDevice, LinuxDeviceType};
-use selinux::setfilecon;
+use resolve::{create_in_root, fd_path, mkdir_in_root, open_in_root};
+use resolve::parent_in_root;
+use selinux::fsetfilecon;
 use std::collections::HashMap;
-use std::fs::{create_dir_all, canonicalize, remove_file};
-use std::os::unix::fs::symlink;
+use std::ffi::CString;
+use std::fs::canonicalize;
 use std::path::{Path, PathBuf};
 
 pub fn init_rootfs(
@@ -19,6 +23,7 @@ pub fn init_rootfs(
     rootfs: &str,
     cpath: &str,
     bind_devices: bool,
+    rootless: bool,
 ) -> Result<()> {
     // set namespace propagation
     let mut flags = MS_REC;
@@ -55,9 +60,8 @@ pub fn init_rootfs(
     mount(None::<&str>, "/", None::<&str>, flags, None::<&str>)?;
 
     for m in &spec.mounts {
-        // TODO: check for nasty destinations involving symlinks and illegal
-        //       locations.
-        // NOTE: this strictly is less permissive than runc, which allows ..
+        // NOTE: symlinks in destinations are resolved inside the rootfs.
+        //       This strictly is less permissive than runc, which allows ..
         //       as long as the resulting path remains in the rootfs. There
         //       is no good reason to allow this so we just forbid it
         if !m.destination.starts_with('/') || m.destination.contains("..") {
@@ -66,7 +70,10 @@ pub fn init_rootfs(
         }
         let (flags, data) = parse_mount(m);
         if m.typ == "cgroup" {
-            mount_cgroups(m, rootfs, flags, &data, &linux.mount_label, cpath)?;
+            let label = &linux.mount_label;
+            let result = mount_cgroups(m, rootfs, flags, &data, label, cpath)
+                .and_then(|_| set_attrs(rootfs, m));
+            skip_rootless(m, flags, result, rootless)?;
         } else if m.destination == "/dev" {
             // dev can't be read only yet because we have to mount devices
             mount_from(
@@ -77,19 +84,19 @@ pub fn init_rootfs(
                 &linux.mount_label,
             )?;
         } else {
-            mount_from(m, rootfs, flags, &data, &linux.mount_label)?;
+            let label = &linux.mount_label;
+            let result = mount_from(m, rootfs, flags, &data, label)
+                .and_then(|_| set_attrs(rootfs, m));
+            skip_rootless(m, flags, result, rootless)?;
         }
     }
 
-    // chdir into the rootfs so we can make devices with simpler paths
-    let olddir = getcwd()?;
-    chdir(rootfs)?;
-
-    default_symlinks()?;
-    create_devices(&linux.devices, bind_devices)?;
-    ensure_ptmx()?;
-
-    chdir(&olddir)?;
+    default_symlinks(rootfs)?;
+    create_devices(rootfs, &linux.devices, bind_devices)?;
+    ensure_ptmx(rootfs)?;
+    if spec.process.terminal {
+        ensure_console(rootfs)?;
+    }
 
     // mount root dir
     mount(
@@ -103,6 +110,45 @@ pub fn init_rootfs(
     Ok(())
 }
 
+// NOTE: filesystems like sysfs, mqueue and cgroup can only be mounted
+//       unprivileged if we own the matching namespace, so rootless
+//       containers skip them instead of failing. Bind mounts only need
+//       access to the source, so they are never skipped, whatever their
+//       type says.
+fn skip_rootless(
+    m: &Mount,
+    flags: MsFlags,
+    result: Result<()>,
+    rootless: bool,
+) -> Result<()> {
+    match result {
+        Err(ref e) if rootless && !flags.contains(MS_BIND) &&
+            is_perm(e) => {
+            warn!(
+                "skipping {} mount of {} in rootless container: {}",
+                &m.typ,
+                &m.destination,
+                e
+            );
+            Ok(())
+        }
+        r => r,
+    }
+}
+
+// checks whether a mount failed with EPERM or EACCES, either directly
+// or wrapped in a message
+fn is_perm(e: &Error) -> bool {
+    let errno = match *e {
+        Error(ErrorKind::Nix(ref n), _) => Some(n.errno()),
+        Error(_, ref state) => state.next_error
+            .as_ref()
+            .and_then(|c| c.downcast_ref::<::nix::Error>())
+            .map(|n| n.errno()),
+    };
+    errno == Some(Errno::EPERM) || errno == Some(Errno::EACCES)
+}
+
 pub fn pivot_rootfs<P: ?Sized + NixPath>(path: &P) -> Result<()> {
     let oldroot = open("/", O_DIRECTORY | O_RDONLY, Mode::empty())?;
     defer!(close(oldroot).unwrap());
@@ -137,6 +183,7 @@ pub fn finish_rootfs(spec: &Spec) -> Result<()> {
                     None::<&str>,
                 )?;
             }
+            set_attrs("/", m)?;
         }
     }
 
@@ -187,8 +234,43 @@ lazy_static! {
         m.insert("nostrictatime", (true, MS_STRICTATIME));
         m
     };
+    // recursive options are applied to submounts with mount_setattr, so
+    // they map to the attributes to set and clear
+    static ref ATTRS: HashMap<&'static str, (u64, u64)> = {
+        let mut m = HashMap::new();
+        m.insert("rro",           (MOUNT_ATTR_RDONLY, 0));
+        m.insert("rrw",           (0, MOUNT_ATTR_RDONLY));
+        m.insert("rnosuid",       (MOUNT_ATTR_NOSUID, 0));
+        m.insert("rsuid",         (0, MOUNT_ATTR_NOSUID));
+        m.insert("rnodev",        (MOUNT_ATTR_NODEV, 0));
+        m.insert("rdev",          (0, MOUNT_ATTR_NODEV));
+        m.insert("rnoexec",       (MOUNT_ATTR_NOEXEC, 0));
+        m.insert("rexec",         (0, MOUNT_ATTR_NOEXEC));
+        m.insert("rnodiratime",   (MOUNT_ATTR_NODIRATIME, 0));
+        m.insert("rdiratime",     (0, MOUNT_ATTR_NODIRATIME));
+        // atime modes are a field, so the old mode has to be cleared
+        m.insert("rrelatime",     (MOUNT_ATTR_RELATIME, MOUNT_ATTR_ATIME));
+        m.insert("rnoatime",      (MOUNT_ATTR_NOATIME, MOUNT_ATTR_ATIME));
+        m.insert("rstrictatime",  (MOUNT_ATTR_STRICTATIME, MOUNT_ATTR_ATIME));
+        m.insert("rnosymfollow",  (MOUNT_ATTR_NOSYMFOLLOW, 0));
+        m.insert("rsymfollow",    (0, MOUNT_ATTR_NOSYMFOLLOW));
+        m
+    };
 }
 
+// attributes for mount_setattr from linux/mount.h
+const MOUNT_ATTR_RDONLY: u64 = 0x00000001;
+const MOUNT_ATTR_NOSUID: u64 = 0x00000002;
+const MOUNT_ATTR_NODEV: u64 = 0x00000004;
+const MOUNT_ATTR_NOEXEC: u64 = 0x00000008;
+const MOUNT_ATTR_ATIME: u64 = 0x00000070;
+const MOUNT_ATTR_RELATIME: u64 = 0x00000000;
+const MOUNT_ATTR_NOATIME: u64 = 0x00000010;
+const MOUNT_ATTR_STRICTATIME: u64 = 0x00000020;
+const MOUNT_ATTR_NODIRATIME: u64 = 0x00000080;
+const MOUNT_ATTR_NOSYMFOLLOW: u64 = 0x00200000;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+
 fn mount_cgroups(
     m: &Mount,
     rootfs: &str,
@@ -197,6 +279,16 @@ fn mount_cgroups(
     label: &str,
     cpath: &str,
 ) -> Result<()> {
+    if cgroups::is_unified() {
+        // the container's own cgroup is bind mounted directly
+        let bm = Mount {
+            source: cgroups::unified_path(cpath).unwrap(),
+            typ: "bind".to_string(),
+            destination: m.destination.clone(),
+            options: Vec::new(),
+        };
+        return mount_from(&bm, rootfs, flags | MS_BIND | MS_REC, data, label);
+    }
     let cm = Mount {
         source: "tmpfs".to_string(),
         typ: "tmpfs".to_string(),
@@ -235,22 +327,15 @@ fn mount_cgroups(
         for k in key.split(',') {
             if k != key {
                 // try to create a symlink for combined strings
-                let dest = format!{"{}{}/{}", rootfs, &m.destination, &k};
-                symlink(key, &dest)?;
+                let dest = format!{"{}/{}", &m.destination, &k};
+                symlink_in_root(rootfs, key, &dest)?;
             }
         }
 
     }
     // remount readonly if necessary
     if flags.contains(MS_RDONLY) {
-        let dest = format!{"{}{}", rootfs, &m.destination};
-        mount(
-            Some(&*dest),
-            &*dest,
-            None::<&str>,
-            cflags | MS_BIND | MS_REMOUNT,
-            None::<&str>,
-        )?;
+        remount(rootfs, &m.destination, cflags | MS_BIND)?;
     }
     Ok(())
 }
@@ -269,13 +354,50 @@ fn parse_mount(m: &Mount) -> (MsFlags, String) {
                 }
             }
             None => {
-                data.push(s.as_str());
+                if !ATTRS.contains_key(s.as_str()) {
+                    data.push(s.as_str());
+                }
             }
         };
     }
     (flags, data.join(","))
 }
 
+fn parse_attrs(m: &Mount) -> (u64, u64) {
+    let mut set = 0;
+    let mut clr = 0;
+    for s in &m.options {
+        if let Some(&(on, off)) = ATTRS.get(s.as_str()) {
+            set = set & !off | on;
+            clr = clr & !on | off;
+        }
+    }
+    (set, clr)
+}
+
+// applies recursive options like rro to a mount and all of its submounts
+fn set_attrs(rootfs: &str, m: &Mount) -> Result<()> {
+    let (set, clr) = parse_attrs(m);
+    if set == 0 && clr == 0 {
+        return Ok(());
+    }
+    let fd = open_in_root(rootfs, &m.destination)?;
+    defer!({ let _ = close(fd); });
+    let flags = libc::AT_EMPTY_PATH as libc::c_uint | AT_RECURSIVE;
+    if let Err(e) = mount_setattr(fd, &CString::new("")?, flags, set, clr) {
+        if e.errno() == Errno::ENOSYS {
+            let msg = format!(
+                "recursive options for {} need mount_setattr (linux 5.12)",
+                &m.destination
+            );
+            bail!(ErrorKind::InvalidSpec(msg));
+        }
+        let msg = format!("could not set attributes of {}", &m.destination);
+        Err(e).chain_err(|| msg)?;
+    }
+    Ok(())
+}
+
 fn mount_from(
     m: &Mount,
     rootfs: &str,
@@ -294,8 +416,6 @@ fn mount_from(
         d = data.to_string();
     }
 
-    let dest = format!{"{}{}", rootfs, &m.destination};
-
     let src = if m.typ == "bind" {
         canonicalize(&m.source)?
     } else {
@@ -309,13 +429,14 @@ fn mount_from(
         &m.typ,
         &d
     );
-    if let Err(e) = create_dir_all(&dest) {
-        debug!(
-            "ignoring create dir fail for mount {}: {}",
-            &m.destination,
-            e
-        )
-    }
+    // NOTE: files can only be bind mounted onto files
+    let fd = if m.typ == "bind" && !src.is_dir() {
+        create_in_root(rootfs, &m.destination)
+    } else {
+        mkdir_in_root(rootfs, &m.destination)
+    }.chain_err(|| format!("could not create {}", &m.destination))?;
+    defer!({ let _ = close(fd); });
+    let dest = fd_path(fd);
     if let Err(e) = mount(
         Some(&*src),
         &*dest,
@@ -331,7 +452,7 @@ fn mount_from(
         // try again without mount label
         mount(Some(&*src), &*dest, Some(&*m.typ), flags, Some(data))?;
         // warn if label cannot be set
-        if let Err(e) = setfilecon(&dest, label) {
+        if let Err(e) = set_label(rootfs, &m.destination, label) {
             warn!{"could not set mount label of {} to {}: {}",
                   &m.destination, &label, e};
         }
@@ -340,60 +461,97 @@ fn mount_from(
     if flags.contains(MS_BIND) &&
         flags.intersects(!(MS_REC | MS_REMOUNT | MS_BIND))
     {
-        mount(
-            Some(&*dest),
-            &*dest,
-            None::<&str>,
-            flags | MS_REMOUNT,
-            None::<&str>,
-        )?;
+        remount(rootfs, &m.destination, flags)?;
     }
     Ok(())
 }
 
+// NOTE: an fd opened before a mount refers to what was mounted over, so
+//       the destination is opened again to get the new mount
+fn remount(rootfs: &str, dest: &str, flags: MsFlags) -> Result<()> {
+    let fd = open_in_root(rootfs, dest)?;
+    defer!({ let _ = close(fd); });
+    let path = fd_path(fd);
+    mount(
+        Some(&*path),
+        &*path,
+        None::<&str>,
+        flags | MS_REMOUNT,
+        None::<&str>,
+    )?;
+    Ok(())
+}
+
+fn set_label(rootfs: &str, dest: &str, label: &str) -> Result<()> {
+    let fd = open_in_root(rootfs, dest)?;
+    defer!({ let _ = close(fd); });
+    fsetfilecon(fd, label)
+}
+
 static SYMLINKS: &'static [(&'static str, &'static str)] =
     &[
-        ("/proc/self/fd", "dev/fd"),
-        ("/proc/self/fd/0", "dev/stdin"),
-        ("/proc/self/fd/1", "dev/stdout"),
-        ("/proc/self/fd/2", "dev/stderr"),
+        ("/proc/self/fd", "/dev/fd"),
+        ("/proc/self/fd/0", "/dev/stdin"),
+        ("/proc/self/fd/1", "/dev/stdout"),
+        ("/proc/self/fd/2", "/dev/stderr"),
     ];
 
-fn default_symlinks() -> Result<()> {
+fn symlink_in_root(rootfs: &str, target: &str, path: &str) -> Result<()> {
+    let (dirfd, name) = parent_in_root(rootfs, path)?;
+    defer!({ let _ = close(dirfd); });
+    symlinkat(&CString::new(target)?, dirfd, &name)?;
+    Ok(())
+}
+
+fn default_symlinks(rootfs: &str) -> Result<()> {
     if Path::new("/proc/kcore").exists() {
-        symlink("/proc/kcore", "dev/kcore")?;
+        symlink_in_root(rootfs, "/proc/kcore", "/dev/kcore")?;
     }
     for &(src, dst) in SYMLINKS {
-        symlink(src, dst)?;
+        symlink_in_root(rootfs, src, dst)?;
     }
     Ok(())
 }
-fn create_devices(devices: &[LinuxDevice], bind: bool) -> Result<()> {
-    let op: fn(&LinuxDevice) -> Result<()> =
+fn create_devices(
+    rootfs: &str,
+    devices: &[LinuxDevice],
+    bind: bool,
+) -> Result<()> {
+    let op: fn(&str, &LinuxDevice) -> Result<()> =
         if bind { bind_dev } else { mknod_dev };
     let old = umask(Mode::from_bits_truncate(0o000));
     for dev in super::DEFAULT_DEVICES.iter() {
-        op(dev)?;
+        op(rootfs, dev)?;
     }
     for dev in devices {
         if !dev.path.starts_with("/dev") || dev.path.contains("..") {
             let msg = format!("{} is not a valid device path", dev.path);
             bail!(ErrorKind::InvalidSpec(msg));
         }
-        op(dev)?;
+        op(rootfs, dev)?;
     }
     umask(old);
     Ok(())
 }
 
-fn ensure_ptmx() -> Result<()> {
-    if let Err(e) = remove_file("dev/ptmx") {
-        if e.kind() != ::std::io::ErrorKind::NotFound {
+fn ensure_ptmx(rootfs: &str) -> Result<()> {
+    let (dirfd, name) = parent_in_root(rootfs, "/dev/ptmx")?;
+    defer!({ let _ = close(dirfd); });
+    if let Err(e) = unlinkat(dirfd, &name) {
+        if e.errno() != Errno::ENOENT {
             let msg = "could not delete /dev/ptmx".to_string();
             Err(e).chain_err(|| msg)?;
         }
     }
-    symlink("pts/ptmx", "dev/ptmx")?;
+    symlinkat(&CString::new("pts/ptmx")?, dirfd, &name)?;
+    Ok(())
+}
+
+// the pty slave is bind mounted over this later
+fn ensure_console(rootfs: &str) -> Result<()> {
+    let fd = create_in_root(rootfs, "/dev/console")
+        .chain_err(|| "could not create /dev/console")?;
+    close(fd)?;
     Ok(())
 }
 
@@ -414,30 +572,34 @@ fn to_sflag(t: LinuxDeviceType) -> Result<SFlag> {
     })
 }
 
-fn mknod_dev(dev: &LinuxDevice) -> Result<()> {
+fn mknod_dev(rootfs: &str, dev: &LinuxDevice) -> Result<()> {
     let f = to_sflag(dev.typ)?;
     debug!("mknoding {}", &dev.path);
-    mknod(
-        &dev.path[1..],
-        f,
-        Mode::from_bits_truncate(dev.file_mode.unwrap_or(0)),
+    let (dirfd, name) = parent_in_root(rootfs, &dev.path)?;
+    defer!({ let _ = close(dirfd); });
+    let mode = Mode::from_bits_truncate(dev.file_mode.unwrap_or(0));
+    mknodat(
+        dirfd,
+        &name,
+        f.bits() | mode.bits(),
         makedev(dev.major, dev.minor),
     )?;
-    chown(&dev.path[1..], dev.uid, dev.gid)?;
+    if dev.uid.is_some() || dev.gid.is_some() {
+        // -1 leaves the owner or group unchanged
+        let uid = dev.uid.unwrap_or(!0);
+        let gid = dev.gid.unwrap_or(!0);
+        fchownat(dirfd, &name, uid, gid)?;
+    }
     Ok(())
 }
 
-fn bind_dev(dev: &LinuxDevice) -> Result<()> {
-    let fd = open(
-        &dev.path[1..],
-        O_RDWR | O_CREAT,
-        Mode::from_bits_truncate(0o644),
-    )?;
-    close(fd)?;
+fn bind_dev(rootfs: &str, dev: &LinuxDevice) -> Result<()> {
+    let fd = create_in_root(rootfs, &dev.path)?;
+    defer!({ let _ = close(fd); });
     debug!("bind mounting {}", &dev.path);
     mount(
         Some(&*dev.path),
-        &dev.path[1..],
+        &*fd_path(fd),
         None::<&str>,
         MS_BIND,
         None::<&str>,
@@ -450,14 +612,18 @@ fn mask_path(path: &str) -> Result<()> {
         let msg = format!("invalid maskedPath: {}", path);
         return Err(ErrorKind::InvalidSpec(msg).into());
     }
-    if let Err(e) = mount(
-        Some("/dev/null"),
-        path,
-        None::<&str>,
-        MS_BIND,
-        None::<&str>,
-    )
-    {
+    // NOTE: this happens after pivot_root, so the rootfs is /
+    let result = open_in_root("/", path).and_then(|fd| {
+        defer!({ let _ = close(fd); });
+        mount(
+            Some("/dev/null"),
+            &*fd_path(fd),
+            None::<&str>,
+            MS_BIND,
+            None::<&str>,
+        )
+    });
+    if let Err(e) = result {
         // ignore ENOENT and ENOTDIR: path to mask doesn't exist
         if e.errno() != Errno::ENOENT && e.errno() != Errno::ENOTDIR {
             let msg = format!("could not mask {}", path);
@@ -473,14 +639,13 @@ fn readonly_path(path: &str) -> Result<()> {
         let msg = format!("invalid readonlyPath: {}", path);
         return Err(ErrorKind::InvalidSpec(msg).into());
     }
-    if let Err(e) = mount(
-        Some(&path[1..]),
-        path,
-        None::<&str>,
-        MS_BIND | MS_REC,
-        None::<&str>,
-    )
-    {
+    // NOTE: this happens after pivot_root, so the rootfs is /
+    let result = open_in_root("/", path).and_then(|fd| {
+        defer!({ let _ = close(fd); });
+        let p = fd_path(fd);
+        mount(Some(&*p), &*p, None::<&str>, MS_BIND | MS_REC, None::<&str>)
+    });
+    if let Err(e) = result {
         // ignore ENOENT: path to make read only doesn't exist
         if e.errno() != Errno::ENOENT {
             let msg = format!("could not readonly {}", path);
@@ -489,12 +654,82 @@ fn readonly_path(path: &str) -> Result<()> {
         debug!("ignoring remount of {} because it doesn't exist", path);
         return Ok(());
     }
-    mount(
-        Some(&path[1..]),
-        &path[1..],
-        None::<&str>,
-        MS_BIND | MS_REC | MS_RDONLY | MS_REMOUNT,
-        None::<&str>,
-    )?;
+    // NOTE: a remount only makes the top mount read only and would leave
+    //       submounts writable, so this needs mount_setattr
+    let fd = open_in_root("/", path)?;
+    defer!({ let _ = close(fd); });
+    let flags = libc::AT_EMPTY_PATH as libc::c_uint | AT_RECURSIVE;
+    let empty = CString::new("")?;
+    if let Err(e) = mount_setattr(fd, &empty, flags, MOUNT_ATTR_RDONLY, 0) {
+        // NOTE: some seccomp profiles reject unknown syscalls with EPERM
+        //       instead of ENOSYS
+        if e.errno() == Errno::ENOSYS || e.errno() == Errno::EPERM {
+            let msg = format!(
+                "readonlyPath {} needs mount_setattr (linux 5.12)",
+                path
+            );
+            bail!(ErrorKind::InvalidSpec(msg));
+        }
+        let msg = format!("could not readonly {}", path);
+        Err(e).chain_err(|| msg)?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(options: &[&str]) -> (u64, u64) {
+        let m = Mount {
+            destination: "/mnt".to_string(),
+            typ: "bind".to_string(),
+            source: "/mnt".to_string(),
+            options: options.iter().map(|s| s.to_string()).collect(),
+        };
+        parse_attrs(&m)
+    }
+
+    #[test]
+    fn no_attrs() {
+        assert_eq!(attrs(&[]), (0, 0));
+        assert_eq!(attrs(&["rbind", "ro", "nosuid", "noatime"]), (0, 0));
+    }
+
+    #[test]
+    fn set_and_clear() {
+        assert_eq!(attrs(&["rro"]), (MOUNT_ATTR_RDONLY, 0));
+        assert_eq!(attrs(&["rrw"]), (0, MOUNT_ATTR_RDONLY));
+        assert_eq!(
+            attrs(&["rbind", "rro", "rnosuid", "rdev"]),
+            (MOUNT_ATTR_RDONLY | MOUNT_ATTR_NOSUID, MOUNT_ATTR_NODEV)
+        );
+    }
+
+    #[test]
+    fn last_option_wins() {
+        assert_eq!(attrs(&["rro", "rrw"]), (0, MOUNT_ATTR_RDONLY));
+        assert_eq!(attrs(&["rrw", "rro"]), (MOUNT_ATTR_RDONLY, 0));
+        assert_eq!(
+            attrs(&["rnosymfollow", "rsymfollow", "rnoexec"]),
+            (MOUNT_ATTR_NOEXEC, MOUNT_ATTR_NOSYMFOLLOW)
+        );
+    }
+
+    #[test]
+    fn atime() {
+        // changing the atime mode always clears the whole field
+        assert_eq!(
+            attrs(&["rnoatime"]),
+            (MOUNT_ATTR_NOATIME, MOUNT_ATTR_ATIME)
+        );
+        assert_eq!(
+            attrs(&["rnoatime", "rrelatime"]),
+            (MOUNT_ATTR_RELATIME, MOUNT_ATTR_ATIME)
+        );
+        assert_eq!(
+            attrs(&["rstrictatime", "rro"]),
+            (MOUNT_ATTR_STRICTATIME | MOUNT_ATTR_RDONLY, MOUNT_ATTR_ATIME)
+        );
+    }
+}
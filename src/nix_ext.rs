@@ -1,11 +1,12 @@
 // Functions in libc that haven't made it into nix yet
 use libc;
-use nix::{Errno, Result};
+use nix::{Errno, Error, Result};
 use std::os::unix::io::RawFd;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::mem::{size_of, zeroed};
 
 #[inline]
-pub fn lsetxattr(
+pub fn setxattr(
     path: &CString,
     name: &CString,
     value: &CString,
@@ -13,7 +14,7 @@ pub fn lsetxattr(
     flags: i32,
 ) -> Result<()> {
     let res = unsafe {
-        libc::lsetxattr(
+        libc::setxattr(
             path.as_ptr(),
             name.as_ptr(),
             value.as_ptr() as *const libc::c_void,
@@ -46,7 +47,7 @@ pub fn setrlimit(
         rlim_cur: soft,
         rlim_max: hard,
     };
-    let res = unsafe { libc::setrlimit(resource, rlim) };
+    let res = unsafe { libc::setrlimit(resource as _, rlim) };
     Errno::result(res).map(drop)
 }
 
@@ -73,3 +74,363 @@ pub fn putenv(string: &CString) -> Result<()> {
     let res = unsafe { libc::putenv(string.as_ptr() as *mut libc::c_char) };
     Errno::result(res).map(drop)
 }
+
+#[inline]
+pub fn eventfd(initval: libc::c_uint, flags: libc::c_int) -> Result<RawFd> {
+    let res = unsafe { libc::eventfd(initval, flags) };
+    Errno::result(res)
+}
+
+#[inline]
+pub fn inotify_init1(flags: libc::c_int) -> Result<RawFd> {
+    let res = unsafe { libc::inotify_init1(flags) };
+    Errno::result(res)
+}
+
+#[inline]
+pub fn inotify_add_watch(fd: RawFd, path: &CString, mask: u32) -> Result<i32> {
+    let res = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+    Errno::result(res)
+}
+
+#[inline]
+pub fn unlockpt(fd: RawFd) -> Result<()> {
+    let res = unsafe { libc::unlockpt(fd) };
+    Errno::result(res).map(drop)
+}
+
+pub fn ptsname(fd: RawFd) -> Result<String> {
+    let mut buf = [0 as libc::c_char; 64];
+    let res = unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) };
+    if res != 0 {
+        return Err(Error::Sys(Errno::from_i32(res)));
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+pub fn socketpair() -> Result<(RawFd, RawFd)> {
+    let mut fds = [-1 as libc::c_int; 2];
+    let res = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    };
+    Errno::result(res).map(|_| (fds[0], fds[1]))
+}
+
+// NOTE: the control buffer is made of u64s so it is aligned for cmsghdr
+fn cmsg_buffer(len: usize) -> Vec<u64> {
+    let space = unsafe { libc::CMSG_SPACE(len as u32) };
+    vec![0; (space as usize + 7) / 8]
+}
+
+// sends fd over a unix socket with SCM_RIGHTS
+pub fn send_fd(sock: RawFd, fd: RawFd) -> Result<()> {
+    let mut data = [0u8; 1];
+    let mut cmsg_buf = cmsg_buffer(size_of::<RawFd>());
+    let res = unsafe {
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = (cmsg_buf.len() * 8) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as _;
+        *(libc::CMSG_DATA(cmsg) as *mut RawFd) = fd;
+        libc::sendmsg(sock, &msg, 0)
+    };
+    Errno::result(res).map(drop)
+}
+
+// receives an fd sent over a unix socket with SCM_RIGHTS
+pub fn recv_fd(sock: RawFd) -> Result<RawFd> {
+    let mut data = [0u8; 1];
+    let mut cmsg_buf = cmsg_buffer(size_of::<RawFd>());
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = (cmsg_buf.len() * 8) as _;
+        let res = libc::recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC);
+        Errno::result(res)?;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if res == 0 || cmsg.is_null() || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            // the other end closed without sending an fd
+            return Err(Error::Sys(Errno::EPIPE));
+        }
+        Ok(*(libc::CMSG_DATA(cmsg) as *const RawFd))
+    }
+}
+
+#[inline]
+pub fn set_passcred(sock: RawFd) -> Result<()> {
+    let on: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            sock,
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &on as *const libc::c_int as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+// receives a byte from a unix socket and returns the pid of the sender.
+// NOTE: the socket must have SO_PASSCRED set before the byte is sent.
+//       The kernel translates the pid into our pid namespace.
+pub fn recv_pid(sock: RawFd) -> Result<libc::pid_t> {
+    let mut data = [0u8; 1];
+    let mut cmsg_buf = cmsg_buffer(size_of::<libc::ucred>());
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = (cmsg_buf.len() * 8) as _;
+        let res = libc::recvmsg(sock, &mut msg, 0);
+        Errno::result(res)?;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if res == 0 || cmsg.is_null() ||
+            (*cmsg).cmsg_type != libc::SCM_CREDENTIALS
+        {
+            // the other end closed without sending anything
+            return Err(Error::Sys(Errno::EPIPE));
+        }
+        let cred = *(libc::CMSG_DATA(cmsg) as *const libc::ucred);
+        Ok(cred.pid)
+    }
+}
+
+#[inline]
+pub fn pidfd_open(pid: libc::pid_t) -> Result<RawFd> {
+    let res = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    Errno::result(res).map(|fd| fd as RawFd)
+}
+
+#[inline]
+pub fn pidfd_getfd(pidfd: RawFd, fd: RawFd) -> Result<RawFd> {
+    let res = unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd, fd, 0) };
+    Errno::result(res).map(|fd| fd as RawFd)
+}
+
+pub fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { zeroed() };
+    let mut result = 0 as *mut libc::passwd;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let res = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if res != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[inline]
+pub fn pidfd_send_signal(pidfd: RawFd, signal: libc::c_int) -> Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd,
+            signal,
+            0 as *const libc::siginfo_t,
+            0,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+#[inline]
+pub fn openat(
+    dirfd: RawFd,
+    path: &CString,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+) -> Result<RawFd> {
+    let res = unsafe { libc::openat(dirfd, path.as_ptr(), flags, mode) };
+    Errno::result(res)
+}
+
+// struct open_how from linux/openat2.h
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+#[inline]
+pub fn openat2(
+    dirfd: RawFd,
+    path: &CString,
+    flags: libc::c_int,
+    resolve: u64,
+) -> Result<RawFd> {
+    let how = OpenHow {
+        flags: flags as u64,
+        mode: 0,
+        resolve: resolve,
+    };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dirfd,
+            path.as_ptr(),
+            &how as *const OpenHow,
+            size_of::<OpenHow>(),
+        )
+    };
+    Errno::result(res).map(|fd| fd as RawFd)
+}
+
+#[inline]
+pub fn mkdirat(dirfd: RawFd, path: &CString, mode: libc::mode_t) -> Result<()> {
+    let res = unsafe { libc::mkdirat(dirfd, path.as_ptr(), mode) };
+    Errno::result(res).map(drop)
+}
+
+#[inline]
+pub fn mknodat(
+    dirfd: RawFd,
+    path: &CString,
+    mode: libc::mode_t,
+    dev: libc::dev_t,
+) -> Result<()> {
+    let res = unsafe { libc::mknodat(dirfd, path.as_ptr(), mode, dev) };
+    Errno::result(res).map(drop)
+}
+
+#[inline]
+pub fn fchownat(
+    dirfd: RawFd,
+    path: &CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+) -> Result<()> {
+    let flags = libc::AT_SYMLINK_NOFOLLOW;
+    let res = unsafe { libc::fchownat(dirfd, path.as_ptr(), uid, gid, flags) };
+    Errno::result(res).map(drop)
+}
+
+#[inline]
+pub fn symlinkat(target: &CString, dirfd: RawFd, path: &CString) -> Result<()> {
+    let res =
+        unsafe { libc::symlinkat(target.as_ptr(), dirfd, path.as_ptr()) };
+    Errno::result(res).map(drop)
+}
+
+#[inline]
+pub fn unlinkat(dirfd: RawFd, path: &CString) -> Result<()> {
+    let res = unsafe { libc::unlinkat(dirfd, path.as_ptr(), 0) };
+    Errno::result(res).map(drop)
+}
+
+// NOTE: an empty path reads the symlink that dirfd was opened on
+pub fn readlinkat(dirfd: RawFd, path: &CString) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    let res = unsafe {
+        libc::readlinkat(
+            dirfd,
+            path.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+        )
+    };
+    let len = Errno::result(res)? as usize;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[inline]
+pub fn memfd_create(name: &CString, flags: libc::c_uint) -> Result<RawFd> {
+    let res = unsafe { libc::memfd_create(name.as_ptr(), flags) };
+    Errno::result(res)
+}
+
+pub fn fexecve(fd: RawFd, args: &[CString], env: &[CString]) -> Result<()> {
+    let mut argv: Vec<*const libc::c_char> =
+        args.iter().map(|s| s.as_ptr()).collect();
+    argv.push(0 as *const libc::c_char);
+    let mut envp: Vec<*const libc::c_char> =
+        env.iter().map(|s| s.as_ptr()).collect();
+    envp.push(0 as *const libc::c_char);
+    let res = unsafe { libc::fexecve(fd, argv.as_ptr(), envp.as_ptr()) };
+    Errno::result(res).map(drop)
+}
+
+pub const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+#[inline]
+pub fn close_range(
+    first: libc::c_uint,
+    last: libc::c_uint,
+    flags: libc::c_uint,
+) -> Result<()> {
+    let res =
+        unsafe { libc::syscall(libc::SYS_close_range, first, last, flags) };
+    Errno::result(res).map(drop)
+}
+
+// struct mount_attr from linux/mount.h
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+#[inline]
+pub fn mount_setattr(
+    dirfd: RawFd,
+    path: &CString,
+    flags: libc::c_uint,
+    set: u64,
+    clr: u64,
+) -> Result<()> {
+    let attr = MountAttr {
+        attr_set: set,
+        attr_clr: clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            dirfd,
+            path.as_ptr(),
+            flags,
+            &attr as *const MountAttr,
+            size_of::<MountAttr>(),
+        )
+    };
+    Errno::result(res).map(drop)
+}
@@ -0,0 +1,87 @@
+// Information about processes read from /proc
+use errors::*;
+use libc;
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct Stat {
+    pub state: char,
+    pub ppid: i32,
+    // clock ticks after boot
+    pub start_time: u64,
+}
+
+fn read(path: &str) -> Result<String> {
+    let mut f = File::open(path).chain_err(
+        || format!("failed to open {}", path),
+    )?;
+    let mut result = String::new();
+    f.read_to_string(&mut result)?;
+    Ok(result)
+}
+
+pub fn stat(pid: i32) -> Result<Stat> {
+    let path = format!("/proc/{}/stat", pid);
+    let data = read(&path)?;
+    // NOTE: the command name can contain spaces and parens, so we
+    //       split after the last paren
+    let rest = match data.rfind(')') {
+        Some(i) => &data[i + 1..],
+        None => bail!("invalid {}", path),
+    };
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    if fields.len() < 20 {
+        bail!("invalid {}", path);
+    }
+    let invalid = || format!("invalid {}", path);
+    Ok(Stat {
+        state: fields[0].chars().next().unwrap_or('?'),
+        ppid: fields[1].parse().chain_err(&invalid)?,
+        start_time: fields[19].parse().chain_err(&invalid)?,
+    })
+}
+
+// returns the real uid of the process
+pub fn uid(pid: i32) -> Result<u32> {
+    let path = format!("/proc/{}/status", pid);
+    let invalid = || format!("invalid {}", path);
+    for line in read(&path)?.lines() {
+        if line.starts_with("Uid:") {
+            if let Some(uid) = line[4..].split_whitespace().next() {
+                return Ok(uid.parse().chain_err(&invalid)?);
+            }
+        }
+    }
+    bail!("no uid in {}", path)
+}
+
+pub fn cmdline(pid: i32) -> Result<String> {
+    let path = format!("/proc/{}/cmdline", pid);
+    let data = read(&path)?;
+    let args: Vec<&str> = data.split('\0').filter(|a| !a.is_empty()).collect();
+    if args.is_empty() {
+        // kernel threads and zombies have no command line
+        let comm = read(&format!("/proc/{}/comm", pid))?;
+        return Ok(format!("[{}]", comm.trim_right()));
+    }
+    Ok(args.join(" "))
+}
+
+fn boot_time() -> Result<u64> {
+    for line in read("/proc/stat")?.lines() {
+        if line.starts_with("btime ") {
+            let btime = line[6..].trim();
+            return Ok(btime.parse().chain_err(|| "invalid btime")?);
+        }
+    }
+    bail!("no btime in /proc/stat")
+}
+
+// converts a start time in clock ticks to wall clock time
+pub fn start_time(ticks: u64) -> Result<SystemTime> {
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    let secs = boot_time()? + ticks / hz;
+    let nanos = (ticks % hz) * 1_000_000_000 / hz;
+    Ok(UNIX_EPOCH + Duration::new(secs, nanos as u32))
+}
@@ -0,0 +1,275 @@
+// Resolution of paths inside the container rootfs. Symlinks in the rootfs
+// are treated as if the rootfs were /, so a malicious image can't point
+// a mount or device at a path on the host. Paths are opened with O_PATH
+// and the resulting fd is used through /proc/self/fd so nothing can be
+// swapped out from under us between resolving a path and using it.
+use libc;
+use nix::{Errno, Error, Result};
+use nix::sys::stat::fstat;
+use nix::unistd::close;
+use nix_ext::{openat, openat2, mkdirat, readlinkat};
+use std::ffi::CString;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+const RESOLVE_IN_ROOT: u64 = 0x10;
+// same as the limit the kernel uses
+const MAX_SYMLINKS: usize = 40;
+
+// set when openat2 is not supported by the kernel or blocked by seccomp
+static NO_OPENAT2: AtomicBool = ATOMIC_BOOL_INIT;
+
+// closes the fds of resolved directories on the way out
+struct Fds(Vec<RawFd>);
+
+impl Drop for Fds {
+    fn drop(&mut self) {
+        for &fd in &self.0 {
+            let _ = close(fd);
+        }
+    }
+}
+
+#[inline]
+fn cstr(s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| Error::InvalidPath)
+}
+
+#[inline]
+pub fn fd_path(fd: RawFd) -> String {
+    format!("/proc/self/fd/{}", fd)
+}
+
+fn components(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn open_root(root: &str) -> Result<RawFd> {
+    let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    openat(libc::AT_FDCWD, &cstr(root)?, flags, 0)
+}
+
+// opens path inside rootfd, returning an O_PATH fd
+fn resolve(rootfd: RawFd, path: &str) -> Result<RawFd> {
+    if !NO_OPENAT2.load(Ordering::Relaxed) {
+        let parts = components(path);
+        let rel = if parts.is_empty() {
+            ".".to_string()
+        } else {
+            parts.join("/")
+        };
+        let flags = libc::O_PATH | libc::O_CLOEXEC;
+        let resolve = RESOLVE_IN_ROOT | RESOLVE_NO_MAGICLINKS;
+        loop {
+            match openat2(rootfd, &cstr(&rel)?, flags, resolve) {
+                // the kernel asks us to retry if there was a rename
+                Err(Error::Sys(Errno::EAGAIN)) => continue,
+                // NOTE: some seccomp profiles reject unknown syscalls
+                //       with EPERM instead of ENOSYS
+                Err(Error::Sys(Errno::ENOSYS)) |
+                Err(Error::Sys(Errno::EPERM)) => break,
+                r => return r,
+            }
+        }
+        debug!("openat2 is not available, resolving paths manually");
+        NO_OPENAT2.store(true, Ordering::Relaxed);
+    }
+    walk(rootfd, path)
+}
+
+// NOTE: this resolves one component at a time without following
+//       symlinks. Symlinks are expanded by hand and .. never goes
+//       above the root, which matches what RESOLVE_IN_ROOT does.
+fn walk(rootfd: RawFd, path: &str) -> Result<RawFd> {
+    let mut dirs = Fds(Vec::new());
+    let mut parts = components(path);
+    parts.reverse();
+    let mut links = 0;
+    while let Some(part) = parts.pop() {
+        if part == ".." {
+            if let Some(fd) = dirs.0.pop() {
+                close(fd)?;
+            }
+            continue;
+        }
+        let cur = *dirs.0.last().unwrap_or(&rootfd);
+        let flags = libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = openat(cur, &cstr(&part)?, flags, 0)?;
+        let st = match fstat(fd) {
+            Ok(st) => st,
+            Err(e) => {
+                close(fd)?;
+                return Err(e);
+            }
+        };
+        if st.st_mode & libc::S_IFMT != libc::S_IFLNK {
+            dirs.0.push(fd);
+            continue;
+        }
+        let target = readlinkat(fd, &cstr("")?);
+        close(fd)?;
+        let target = String::from_utf8(target?)
+            .map_err(|_| Error::InvalidPath)?;
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(Error::Sys(Errno::ELOOP));
+        }
+        if target.starts_with('/') {
+            for fd in dirs.0.drain(..) {
+                close(fd)?;
+            }
+        }
+        let mut more = components(&target);
+        more.reverse();
+        parts.extend(more);
+    }
+    match dirs.0.pop() {
+        Some(fd) => Ok(fd),
+        None => {
+            let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+            openat(rootfd, &cstr(".")?, flags, 0)
+        }
+    }
+}
+
+// opens an existing path inside root
+pub fn open_in_root(root: &str, path: &str) -> Result<RawFd> {
+    let rootfd = open_root(root)?;
+    defer!({ let _ = close(rootfd); });
+    resolve(rootfd, path)
+}
+
+fn mkdir_all(rootfd: RawFd, path: &str) -> Result<RawFd> {
+    let mut parent = Fds(vec![resolve(rootfd, "/")?]);
+    let mut current = String::new();
+    for part in components(path) {
+        current.push('/');
+        current.push_str(&part);
+        let next = match resolve(rootfd, &current) {
+            Err(Error::Sys(Errno::ENOENT)) => {
+                // NOTE: the parent is already resolved, so the new
+                //       directory is always created inside the root
+                mkdirat(parent.0[0], &cstr(&part)?, 0o755)?;
+                resolve(rootfd, &current)?
+            }
+            r => r?,
+        };
+        close(parent.0[0])?;
+        parent.0[0] = next;
+    }
+    Ok(parent.0.pop().unwrap())
+}
+
+// opens the directory at path inside root, creating it if necessary
+pub fn mkdir_in_root(root: &str, path: &str) -> Result<RawFd> {
+    let rootfd = open_root(root)?;
+    defer!({ let _ = close(rootfd); });
+    mkdir_all(rootfd, path)
+}
+
+// opens the parent of path inside root, creating it if necessary, and
+// returns it with the final component of path
+pub fn parent_in_root(root: &str, path: &str) -> Result<(RawFd, CString)> {
+    let mut parts = components(path);
+    let name = match parts.pop() {
+        Some(name) => name,
+        None => return Err(Error::Sys(Errno::EINVAL)),
+    };
+    let fd = mkdir_in_root(root, &parts.join("/"))?;
+    Ok((fd, cstr(&name)?))
+}
+
+// opens the file at path inside root, creating an empty one if necessary
+pub fn create_in_root(root: &str, path: &str) -> Result<RawFd> {
+    let rootfd = open_root(root)?;
+    defer!({ let _ = close(rootfd); });
+    match resolve(rootfd, path) {
+        Err(Error::Sys(Errno::ENOENT)) => {}
+        r => return r,
+    }
+    let (dirfd, name) = parent_in_root(root, path)?;
+    defer!({ let _ = close(dirfd); });
+    // O_NOFOLLOW makes this fail on a dangling symlink
+    let flags = libc::O_CREAT | libc::O_WRONLY | libc::O_NOFOLLOW |
+        libc::O_CLOEXEC;
+    close(openat(dirfd, &name, flags, 0o644)?)?;
+    resolve(rootfd, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{create_dir_all, read_link, remove_dir_all, File};
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    // builds a small rootfs and returns its path
+    fn rootfs(name: &str) -> String {
+        let root = format!(
+            "{}/railcar-{}-{}",
+            env::temp_dir().display(),
+            name,
+            process::id()
+        );
+        let _ = remove_dir_all(&root);
+        create_dir_all(format!("{}/a/b", root)).unwrap();
+        File::create(format!("{}/a/b/f", root)).unwrap();
+        symlink("b", format!("{}/a/rel", root)).unwrap();
+        symlink("/a/b", format!("{}/abs", root)).unwrap();
+        symlink("../../../../a", format!("{}/a/b/up", root)).unwrap();
+        symlink("/../../a/b/f", format!("{}/file", root)).unwrap();
+        symlink("loop", format!("{}/loop", root)).unwrap();
+        symlink(format!("{}/a", root), format!("{}/host", root)).unwrap();
+        root
+    }
+
+    fn check(root: &str, path: &str, expected: &str) {
+        let rootfd = open_root(root).unwrap();
+        let fd = walk(rootfd, path).unwrap();
+        let got = read_link(fd_path(fd)).unwrap();
+        close(fd).unwrap();
+        close(rootfd).unwrap();
+        assert_eq!(got.to_str().unwrap(), format!("{}{}", root, expected));
+    }
+
+    fn check_err(root: &str, path: &str, errno: Errno) {
+        let rootfd = open_root(root).unwrap();
+        let res = walk(rootfd, path);
+        close(rootfd).unwrap();
+        match res {
+            Err(Error::Sys(e)) => assert_eq!(e, errno),
+            r => panic!("expected {:?} for {}, got {:?}", errno, path, r),
+        }
+    }
+
+    #[test]
+    fn walk_paths() {
+        let root = rootfs("walk");
+        check(&root, "/", "");
+        check(&root, "a/./b//f", "/a/b/f");
+        check(&root, "/a/rel/f", "/a/b/f");
+        check(&root, "/abs/f", "/a/b/f");
+        check(&root, "/file", "/a/b/f");
+        check(&root, "/a/b/up/b", "/a/b");
+        check_err(&root, "/a/missing", Errno::ENOENT);
+        check_err(&root, "/loop", Errno::ELOOP);
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn walk_stays_in_root() {
+        let root = rootfs("clamp");
+        check(&root, "..", "");
+        check(&root, "/../../..", "");
+        check(&root, "/a/../../a/b", "/a/b");
+        check(&root, "/abs/../../../..", "");
+        // a link to the rootfs' path on the host is not followed there
+        check_err(&root, "/host", Errno::ENOENT);
+        remove_dir_all(&root).unwrap();
+    }
+}
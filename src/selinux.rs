@@ -2,8 +2,9 @@ use errors::*;
 use nix::sys::stat::Mode;
 use nix::fcntl::{open, O_RDWR};
 use nix::unistd::{close, write};
-use nix_ext::lsetxattr;
+use nix_ext::setxattr;
 use std::ffi::CString;
+use std::os::unix::io::RawFd;
 
 const EXEC_PATH: &'static str = "/proc/self/attr/exec";
 
@@ -16,10 +17,11 @@ pub fn setexeccon(label: &str) -> Result<()> {
 
 const XATTR_NAME: &'static str = "security.selinux";
 
-pub fn setfilecon(file: &str, label: &str) -> Result<()> {
-    let path = CString::new(file)?;
+// sets the label of the file that fd refers to, which may be an O_PATH fd
+pub fn fsetfilecon(fd: RawFd, label: &str) -> Result<()> {
+    let path = CString::new(format!("/proc/self/fd/{}", fd))?;
     let name = CString::new(XATTR_NAME)?;
     let value = CString::new(label)?;
-    lsetxattr(&path, &name, &value, label.len(), 0)?;
+    setxattr(&path, &name, &value, label.len(), 0)?;
     Ok(())
 }
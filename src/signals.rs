@@ -1,11 +1,19 @@
 use errors::*;
-use nix::c_int;
+use nix::{c_int, Errno};
 use nix::sys::signal::{SigAction, SigHandler, SaFlags, SigSet, Signal};
 use nix::sys::signal::{sigaction, kill, raise};
+use nix::unistd::close;
+use nix_ext::{pidfd_open, pidfd_send_signal};
+use procfs;
+use std::os::unix::io::RawFd;
 
 pub fn pass_signals(child_pid: i32) -> Result<()> {
     unsafe {
         CHILD_PID = child_pid;
+        CHILD_PIDFD = match open_pidfd(child_pid) {
+            Ok(Some(fd)) => fd,
+            _ => -1,
+        };
         set_handler(SigHandler::Handler(child_handler))?;
     }
     Ok(())
@@ -15,13 +23,32 @@ pub fn pass_signals(child_pid: i32) -> Result<()> {
 // a signal to. We store the child's pid in a global variable.
 // The child pid is only set once prior to setting up the
 // signal handler, so it should be safe to access it from the
-// signal handler.
+// signal handler. If the kernel supports pidfds, we signal
+// through one so that the signal can't go to a reused pid.
 static mut CHILD_PID: i32 = 0;
+static mut CHILD_PIDFD: RawFd = -1;
 
 
 extern "C" fn child_handler(signo: c_int) {
     unsafe {
-        let _ = kill(CHILD_PID, Signal::from_c_int(signo).unwrap());
+        if CHILD_PIDFD != -1 {
+            let _ = pidfd_send_signal(CHILD_PIDFD, signo);
+        } else {
+            let _ = kill(CHILD_PID, Signal::from_c_int(signo).unwrap());
+        }
+    }
+}
+
+// returns None if the kernel doesn't support pidfds
+pub fn open_pidfd(pid: i32) -> Result<Option<RawFd>> {
+    match pidfd_open(pid) {
+        Ok(fd) => Ok(Some(fd)),
+        Err(e) => {
+            if e.errno() == Errno::ENOSYS {
+                return Ok(None);
+            }
+            Err(e)?
+        }
     }
 }
 
@@ -95,11 +122,46 @@ pub fn to_signal(signal: &str) -> Result<Signal> {
 }
 
 
+// NOTE: a pidfd refers to a single process, so broadcasts to a process
+//       group or to -1 still go through kill
 pub fn signal_process<T: Into<Option<Signal>>>(
     pid: i32,
     signal: T,
 ) -> Result<()> {
-    kill(pid, signal)?;
+    if pid <= 0 {
+        kill(pid, signal)?;
+        return Ok(());
+    }
+    signal_started(pid, 0, signal)
+}
+
+// NOTE: the process must have been started at start_time, so a reused
+//       pid is reported as ESRCH. A start time of zero skips the check.
+pub fn signal_started<T: Into<Option<Signal>>>(
+    pid: i32,
+    start_time: u64,
+    signal: T,
+) -> Result<()> {
+    // opening the pidfd first pins the process, so it can't be replaced
+    // between the start time check and the signal
+    let pidfd = open_pidfd(pid)?;
+    defer!(if let Some(fd) = pidfd {
+        let _ = close(fd);
+    });
+    if start_time != 0 {
+        let current = procfs::stat(pid).map(|s| s.start_time).unwrap_or(0);
+        if current != start_time {
+            Err(::nix::Error::Sys(Errno::ESRCH))?;
+        }
+    }
+    let signal = signal.into();
+    match pidfd {
+        Some(fd) => {
+            let signo = signal.map_or(0, |s| s as c_int);
+            pidfd_send_signal(fd, signo)?;
+        }
+        None => kill(pid, signal)?,
+    }
     Ok(())
 }
 
@@ -0,0 +1,226 @@
+// Default configuration for the spec command, which is roughly the same
+// as the one generated by runc.
+use nix::unistd::{getgid, getuid};
+use oci::{Linux, LinuxCapabilityType, LinuxDeviceCgroup, LinuxDeviceType};
+use oci::{LinuxIDMapping, LinuxNamespace, LinuxNamespaceType};
+use oci::{LinuxResources, LinuxRlimit, LinuxRlimitType, Mount, Platform};
+use oci::{Process, Root, Spec, User};
+use std::collections::HashMap;
+
+fn mount(dest: &str, typ: &str, source: &str, options: &[&str]) -> Mount {
+    Mount {
+        destination: dest.to_string(),
+        typ: typ.to_string(),
+        source: source.to_string(),
+        options: options.iter().map(|o| o.to_string()).collect(),
+    }
+}
+
+fn namespace(typ: LinuxNamespaceType) -> LinuxNamespace {
+    LinuxNamespace {
+        typ: typ,
+        path: "".to_string(),
+    }
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+fn arch() -> String {
+    // NOTE: the spec uses go architecture names
+    match ::std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64",
+        "arm" => "arm",
+        a => a,
+    }.to_string()
+}
+
+pub fn default_spec(rootless: bool) -> Spec {
+    let mut mounts = vec![
+        mount("/proc", "proc", "proc", &[]),
+        mount(
+            "/dev",
+            "tmpfs",
+            "tmpfs",
+            &["nosuid", "strictatime", "mode=755", "size=65536k"],
+        ),
+        mount(
+            "/dev/pts",
+            "devpts",
+            "devpts",
+            &[
+                "nosuid",
+                "noexec",
+                "newinstance",
+                "ptmxmode=0666",
+                "mode=0620",
+                "gid=5",
+            ],
+        ),
+        mount(
+            "/dev/shm",
+            "tmpfs",
+            "shm",
+            &["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"],
+        ),
+        mount(
+            "/dev/mqueue",
+            "mqueue",
+            "mqueue",
+            &["nosuid", "noexec", "nodev"],
+        ),
+        mount("/sys", "sysfs", "sysfs", &["nosuid", "noexec", "nodev", "ro"]),
+        mount(
+            "/sys/fs/cgroup",
+            "cgroup",
+            "cgroup",
+            &["nosuid", "noexec", "nodev", "relatime", "ro"],
+        ),
+    ];
+    let mut namespaces = vec![
+        namespace(LinuxNamespaceType::pid),
+        namespace(LinuxNamespaceType::network),
+        namespace(LinuxNamespaceType::ipc),
+        namespace(LinuxNamespaceType::uts),
+        namespace(LinuxNamespaceType::mount),
+    ];
+    let mut uid_mappings = Vec::new();
+    let mut gid_mappings = Vec::new();
+    let mut resources = Some(LinuxResources {
+        devices: vec![
+            LinuxDeviceCgroup {
+                allow: false,
+                typ: LinuxDeviceType::a,
+                major: None,
+                minor: None,
+                access: "rwm".to_string(),
+            },
+        ],
+        ..Default::default()
+    });
+
+    if rootless {
+        // an unprivileged user can't setup networking, mount sysfs or
+        // cgroups, or set the gid of devpts
+        namespaces.retain(|ns| match ns.typ {
+            LinuxNamespaceType::network => false,
+            _ => true,
+        });
+        namespaces.push(namespace(LinuxNamespaceType::user));
+        mounts.retain(|m| m.typ != "cgroup");
+        for m in &mut mounts {
+            if m.typ == "devpts" {
+                m.options.retain(|o| o != "gid=5");
+            } else if m.typ == "sysfs" {
+                *m = mount(
+                    "/sys",
+                    "none",
+                    "/sys",
+                    &["rbind", "nosuid", "noexec", "nodev", "ro"],
+                );
+            }
+        }
+        uid_mappings.push(LinuxIDMapping {
+            host_id: getuid(),
+            container_id: 0,
+            size: 1,
+        });
+        gid_mappings.push(LinuxIDMapping {
+            host_id: getgid(),
+            container_id: 0,
+            size: 1,
+        });
+        resources = None;
+    }
+
+    Spec {
+        version: "1.0.0-rc3".to_string(),
+        platform: Platform {
+            os: ::std::env::consts::OS.to_string(),
+            arch: arch(),
+        },
+        process: Process {
+            terminal: true,
+            console_size: ::oci::Box::default(),
+            user: User {
+                uid: 0,
+                gid: 0,
+                additional_gids: Vec::new(),
+                username: "".to_string(),
+            },
+            args: strings(&["sh"]),
+            env: strings(
+                &[
+                    "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:\
+                     /usr/bin:/sbin:/bin",
+                    "TERM=xterm",
+                ],
+            ),
+            cwd: "/".to_string(),
+            capabilities: vec![
+                LinuxCapabilityType::CAP_AUDIT_WRITE,
+                LinuxCapabilityType::CAP_KILL,
+                LinuxCapabilityType::CAP_NET_BIND_SERVICE,
+            ],
+            rlimits: vec![
+                LinuxRlimit {
+                    typ: LinuxRlimitType::RLIMIT_NOFILE,
+                    hard: 1024,
+                    soft: 1024,
+                },
+            ],
+            no_new_privileges: true,
+            apparmor_profile: "".to_string(),
+            selinux_label: "".to_string(),
+        },
+        root: Root {
+            path: "rootfs".to_string(),
+            readonly: true,
+        },
+        hostname: "railcar".to_string(),
+        mounts: mounts,
+        hooks: None,
+        annotations: HashMap::new(),
+        linux: Some(Linux {
+            uid_mappings: uid_mappings,
+            gid_mappings: gid_mappings,
+            sysctl: HashMap::new(),
+            resources: resources,
+            cgroups_path: "".to_string(),
+            namespaces: namespaces,
+            devices: Vec::new(),
+            seccomp: None,
+            rootfs_propagation: "".to_string(),
+            masked_paths: strings(
+                &[
+                    "/proc/acpi",
+                    "/proc/asound",
+                    "/proc/kcore",
+                    "/proc/keys",
+                    "/proc/latency_stats",
+                    "/proc/timer_list",
+                    "/proc/timer_stats",
+                    "/proc/sched_debug",
+                    "/proc/scsi",
+                    "/sys/firmware",
+                ],
+            ),
+            readonly_paths: strings(
+                &[
+                    "/proc/bus",
+                    "/proc/fs",
+                    "/proc/irq",
+                    "/proc/sys",
+                    "/proc/sysrq-trigger",
+                ],
+            ),
+            mount_label: "".to_string(),
+        }),
+        solaris: None,
+        windows: None,
+    }
+}
@@ -1,5 +1,6 @@
 use nix::fcntl::O_CLOEXEC;
-use nix::unistd::{pipe2, read, close};
+use nix::unistd::{pipe2, read, write, close};
+use nix_ext::{socketpair, send_fd, recv_fd, set_passcred, recv_pid};
 use std::os::unix::io::RawFd;
 use super::Result;
 
@@ -27,3 +28,65 @@ impl Cond {
         Ok(())
     }
 }
+
+// passes a single file descriptor from the child to the parent
+pub struct FdSocket {
+    parent: RawFd,
+    child: RawFd,
+}
+
+impl FdSocket {
+    pub fn new() -> Result<FdSocket> {
+        let (parent, child) = socketpair()?;
+        Ok(FdSocket {
+            parent: parent,
+            child: child,
+        })
+    }
+
+    pub fn send(&self, fd: RawFd) -> Result<()> {
+        close(self.parent)?;
+        send_fd(self.child, fd)?;
+        close(self.child)?;
+        Ok(())
+    }
+
+    pub fn recv(&self) -> Result<RawFd> {
+        close(self.child)?;
+        let fd = recv_fd(self.parent)?;
+        close(self.parent)?;
+        Ok(fd)
+    }
+}
+
+// passes the pid of the child to the parent. The pid the parent receives
+// is valid in its pid namespace even if the child is in another one.
+pub struct PidSocket {
+    parent: RawFd,
+    child: RawFd,
+}
+
+impl PidSocket {
+    pub fn new() -> Result<PidSocket> {
+        let (parent, child) = socketpair()?;
+        set_passcred(parent)?;
+        Ok(PidSocket {
+            parent: parent,
+            child: child,
+        })
+    }
+
+    pub fn send(&self) -> Result<()> {
+        close(self.parent)?;
+        write(self.child, &[0])?;
+        close(self.child)?;
+        Ok(())
+    }
+
+    pub fn recv(&self) -> Result<i32> {
+        close(self.child)?;
+        let pid = recv_pid(self.parent)?;
+        close(self.parent)?;
+        Ok(pid)
+    }
+}
@@ -0,0 +1,882 @@
+// Support for the systemd cgroup driver. Systemd is asked to create a
+// transient scope for the container over D-Bus, so this contains just
+// enough of the D-Bus wire protocol to call StartTransientUnit.
+use cgroups;
+use errors::*;
+use nix::unistd::getuid;
+use oci::LinuxResources;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const DEFAULT_ADDRESS: &'static str = "unix:path=/run/dbus/system_bus_socket";
+// seconds to wait for systemd to finish starting a unit
+const JOB_TIMEOUT: u64 = 30;
+
+// message types
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+// header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+// cgroups paths for systemd are in the form slice:prefix:name
+pub fn is_systemd_path(cgroups_path: &str) -> bool {
+    cgroups_path.split(':').count() == 3
+}
+
+fn parse(cgroups_path: &str) -> Result<(String, String)> {
+    let parts: Vec<&str> = cgroups_path.split(':').collect();
+    // NOTE: the parts end up in a path, so they can't contain a /
+    if parts.len() != 3 || parts.iter().any(|p| p.contains('/')) ||
+        parts[2].is_empty()
+    {
+        let msg = format!(
+            "expected systemd cgroups path in the form slice:prefix:name, \
+             got {}",
+            cgroups_path
+        );
+        return Err(ErrorKind::InvalidSpec(msg).into());
+    }
+    let slice = if parts[0].is_empty() {
+        "system.slice"
+    } else {
+        parts[0]
+    };
+    let unit = if parts[1].is_empty() {
+        format!("{}.scope", parts[2])
+    } else {
+        format!("{}-{}.scope", parts[1], parts[2])
+    };
+    Ok((slice.to_string(), unit))
+}
+
+// converts a slice name into its path, so a-b.slice is /a.slice/a-b.slice
+fn expand_slice(slice: &str) -> Result<String> {
+    let invalid = || {
+        let msg = format!("invalid slice name {}", slice);
+        Error::from(ErrorKind::InvalidSpec(msg))
+    };
+    if !slice.ends_with(".slice") || slice.contains('/') {
+        return Err(invalid());
+    }
+    let name = &slice[..slice.len() - ".slice".len()];
+    if name == "-" {
+        return Ok("".to_string());
+    }
+    let mut path = String::new();
+    let mut prefix = String::new();
+    for part in name.split('-') {
+        if part.is_empty() {
+            return Err(invalid());
+        }
+        prefix.push_str(part);
+        path.push_str(&format!("/{}.slice", prefix));
+        prefix.push('-');
+    }
+    Ok(path)
+}
+
+// returns the path of the cgroup that systemd creates for the scope
+pub fn expand_path(cgroups_path: &str) -> Result<String> {
+    let (slice, unit) = parse(cgroups_path)?;
+    Ok(format!("{}/{}", expand_slice(&slice)?, unit))
+}
+
+// NOTE: values are translated to the properties for the cgroup version
+//       in use. Anything that systemd doesn't handle is still written
+//       directly to the cgroup by cgroups::apply.
+fn properties(r: &LinuxResources, w: &mut Writer) -> usize {
+    let unified = cgroups::is_unified();
+    let mut count = 0;
+    if let Some(limit) = r.memory.as_ref().and_then(|m| m.limit) {
+        if limit > 0 {
+            let key = if unified { "MemoryMax" } else { "MemoryLimit" };
+            w.property_u64(key, limit as u64);
+            count += 1;
+        }
+    }
+    if let Some(ref cpu) = r.cpu {
+        if let Some(shares) = cpu.shares {
+            if shares != 0 {
+                if unified {
+                    w.property_u64("CPUWeight", cgroups::cpu_weight(shares));
+                } else {
+                    w.property_u64("CPUShares", shares);
+                }
+                count += 1;
+            }
+        }
+        let quota = cpu.quota.unwrap_or(0);
+        if quota > 0 {
+            let period = cpu.period.unwrap_or(100000);
+            let period = if period == 0 { 100000 } else { period };
+            let per_sec = quota as u64 * 1000000 / period;
+            w.property_u64("CPUQuotaPerSecUSec", per_sec);
+            count += 1;
+        }
+    }
+    if let Some(ref pids) = r.pids {
+        if pids.limit > 0 {
+            w.property_u64("TasksMax", pids.limit as u64);
+            count += 1;
+        }
+    }
+    if let Some(weight) = r.block_io.as_ref().and_then(|b| b.weight) {
+        if weight != 0 {
+            if unified {
+                let weight = cgroups::io_weight(weight) as u64;
+                w.property_u64("IOWeight", weight);
+            } else {
+                w.property_u64("BlockIOWeight", weight as u64);
+            }
+            count += 1;
+        }
+    }
+    count
+}
+
+// asks systemd to create a scope for the cgroups path containing pid
+pub fn start_unit(
+    cgroups_path: &str,
+    pid: i32,
+    resources: &Option<LinuxResources>,
+) -> Result<()> {
+    let (slice, unit) = parse(cgroups_path)?;
+    debug!("starting systemd unit {} in {}", unit, slice);
+    let mut bus = Bus::connect()?;
+    // NOTE: we subscribe before starting the unit so that the signal for
+    //       its job can't be missed
+    let mut rule = Writer::new();
+    rule.string(
+        "type='signal',sender='org.freedesktop.systemd1',\
+         interface='org.freedesktop.systemd1.Manager',member='JobRemoved'",
+    );
+    bus.call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "AddMatch",
+        "s",
+        &rule.buf,
+    ).chain_err(|| "failed to subscribe to systemd jobs")?;
+
+    let mut w = Writer::new();
+    w.string(&unit);
+    w.string("replace");
+    // properties are a(sv)
+    let start = w.begin_array(8);
+    w.struct_start();
+    w.string("Description");
+    w.variant_string(&format!("railcar container {}", unit));
+    w.struct_start();
+    w.string("Slice");
+    w.variant_string(&slice);
+    w.struct_start();
+    w.string("Delegate");
+    w.signature("b");
+    w.u32(1);
+    w.struct_start();
+    w.string("DefaultDependencies");
+    w.signature("b");
+    w.u32(0);
+    w.struct_start();
+    w.string("PIDs");
+    w.signature("au");
+    let pids = w.begin_array(4);
+    w.u32(pid as u32);
+    w.end_array(pids);
+    if let Some(ref r) = *resources {
+        properties(r, &mut w);
+    }
+    w.end_array(start);
+    // aux is a(sa(sv)) and unused
+    let aux = w.begin_array(8);
+    w.end_array(aux);
+
+    let reply = bus.call(
+        "org.freedesktop.systemd1",
+        "/org/freedesktop/systemd1",
+        "org.freedesktop.systemd1.Manager",
+        "StartTransientUnit",
+        "ssa(sv)a(sa(sv))",
+        &w.buf,
+    ).chain_err(|| format!("failed to start unit {}", unit))?;
+    let job = reply.reader().string()?;
+    // NOTE: cgroups::apply must not run until systemd has created the
+    //       cgroup and moved pid into it, or systemd could move pid back
+    //       out of the cgroups we set up. If systemd is too slow, we carry
+    //       on and cgroups::apply creates the cgroup itself.
+    match bus.wait_job(&job, Duration::from_secs(JOB_TIMEOUT)) {
+        Ok(ref result) if result == "done" => Ok(()),
+        Ok(result) => bail!("job for unit {} failed: {}", unit, result),
+        Err(Error(ErrorKind::Io(ref e), _))
+            if e.kind() == std::io::ErrorKind::WouldBlock ||
+                e.kind() == std::io::ErrorKind::TimedOut =>
+        {
+            warn!("timed out waiting for unit {}, continuing", unit);
+            Ok(())
+        }
+        Err(e) => Err(e).chain_err(|| format!("failed to start {}", unit)),
+    }
+}
+
+// NOTE: the bus can be changed with DBUS_SYSTEM_BUS_ADDRESS, which is
+//       also useful for testing against a fake bus
+fn bus_path() -> Result<String> {
+    let address = env::var("DBUS_SYSTEM_BUS_ADDRESS")
+        .unwrap_or_else(|_| DEFAULT_ADDRESS.to_string());
+    // the address may contain multiple ; separated entries
+    for entry in address.split(';') {
+        if !entry.starts_with("unix:") {
+            continue;
+        }
+        for kv in entry["unix:".len()..].split(',') {
+            if kv.starts_with("path=") {
+                return Ok(kv["path=".len()..].to_string());
+            }
+        }
+    }
+    bail!("no supported unix path in bus address {}", address)
+}
+
+struct Bus {
+    stream: BufReader<UnixStream>,
+    serial: u32,
+    // signals that were received while waiting for a reply
+    signals: Vec<Message>,
+}
+
+impl Bus {
+    fn connect() -> Result<Bus> {
+        let path = bus_path()?;
+        let mut stream = UnixStream::connect(&path)
+            .chain_err(|| format!("failed to connect to {}", path))?;
+        // authenticate with our uid, which is sent hex encoded
+        let uid = getuid().to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        stream.write_all(b"\0")?;
+        stream.write_all(format!("AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+        let mut stream = BufReader::new(stream);
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            bail!("d-bus authentication failed: {}", line.trim());
+        }
+        stream.get_mut().write_all(b"BEGIN\r\n")?;
+        let mut bus = Bus {
+            stream: stream,
+            serial: 0,
+            signals: Vec::new(),
+        };
+        bus.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            "",
+            &[],
+        )?;
+        Ok(bus)
+    }
+
+    fn call(
+        &mut self,
+        dest: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<Message> {
+        self.serial += 1;
+        let mut w = Writer::new();
+        w.u8(b'l');
+        w.u8(METHOD_CALL);
+        w.u8(0);
+        w.u8(1);
+        w.u32(body.len() as u32);
+        w.u32(self.serial);
+        let fields = w.begin_array(8);
+        w.field(FIELD_PATH, "o", path);
+        w.field(FIELD_DESTINATION, "s", dest);
+        w.field(FIELD_INTERFACE, "s", interface);
+        w.field(FIELD_MEMBER, "s", member);
+        if !signature.is_empty() {
+            w.struct_start();
+            w.u8(FIELD_SIGNATURE);
+            w.signature("g");
+            w.signature(signature);
+        }
+        w.end_array(fields);
+        w.align(8);
+        w.buf.extend_from_slice(body);
+        self.stream.get_mut().write_all(&w.buf)?;
+
+        // skip anything else until we get our reply
+        loop {
+            let msg = self.read_message()?;
+            if msg.typ == SIGNAL {
+                self.signals.push(msg);
+                continue;
+            }
+            if msg.reply_serial != self.serial {
+                continue;
+            }
+            match msg.typ {
+                METHOD_RETURN => return Ok(msg),
+                ERROR => bail!("{}: {}", msg.error_name, msg.error_message),
+                _ => {}
+            }
+        }
+    }
+
+    // waits for the JobRemoved signal of job and returns its result
+    fn wait_job(&mut self, job: &str, timeout: Duration) -> Result<String> {
+        self.stream.get_ref().set_read_timeout(Some(timeout))?;
+        loop {
+            let msg = if self.signals.is_empty() {
+                self.read_message()?
+            } else {
+                self.signals.remove(0)
+            };
+            if msg.typ != SIGNAL || msg.member != "JobRemoved" {
+                continue;
+            }
+            // the body is the job id, job path, unit and result
+            let mut r = msg.reader();
+            r.u32()?;
+            if r.string()? != job {
+                continue;
+            }
+            r.string()?;
+            return r.string();
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Message> {
+        let mut fixed = [0u8; 16];
+        self.stream.read_exact(&mut fixed)?;
+        let little = match fixed[0] {
+            b'l' => true,
+            b'B' => false,
+            _ => bail!("invalid d-bus message"),
+        };
+        let body_len = read_u32(&fixed[4..8], little) as usize;
+        let fields_len = read_u32(&fixed[12..16], little) as usize;
+        let padded = (fields_len + 7) & !7;
+        let mut rest = vec![0u8; padded + body_len];
+        self.stream.read_exact(&mut rest)?;
+        // offsets are relative to the start of the message
+        let mut data = fixed.to_vec();
+        data.extend(rest);
+        let mut r = Reader {
+            data: &data,
+            pos: 16,
+            little: little,
+        };
+        let mut msg = Message {
+            typ: fixed[1],
+            reply_serial: 0,
+            member: String::new(),
+            error_name: String::new(),
+            error_message: String::new(),
+            little: little,
+            body: Vec::new(),
+        };
+        let mut signature = String::new();
+        while r.pos < 16 + fields_len {
+            r.align(8);
+            let code = r.u8()?;
+            let sig = r.signature()?;
+            match &*sig {
+                "s" | "o" => {
+                    let value = r.string()?;
+                    match code {
+                        FIELD_MEMBER => msg.member = value,
+                        FIELD_ERROR_NAME => msg.error_name = value,
+                        _ => {}
+                    }
+                }
+                "g" => {
+                    let value = r.signature()?;
+                    if code == FIELD_SIGNATURE {
+                        signature = value;
+                    }
+                }
+                "u" => {
+                    let value = r.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        msg.reply_serial = value;
+                    }
+                }
+                _ => bail!("unexpected d-bus header field type {}", sig),
+            }
+        }
+        msg.body = data[16 + padded..].to_vec();
+        if msg.typ == ERROR && signature.starts_with('s') {
+            msg.error_message = msg.reader().string()?;
+        }
+        Ok(msg)
+    }
+}
+
+struct Message {
+    typ: u8,
+    reply_serial: u32,
+    member: String,
+    error_name: String,
+    error_message: String,
+    little: bool,
+    body: Vec<u8>,
+}
+
+impl Message {
+    // NOTE: the body starts 8 byte aligned, so alignment within the body
+    //       is the same as within the message
+    fn reader<'a>(&'a self) -> Reader<'a> {
+        Reader {
+            data: &self.body,
+            pos: 0,
+            little: self.little,
+        }
+    }
+}
+
+fn read_u32(b: &[u8], little: bool) -> u32 {
+    let (b0, b1, b2, b3) = (b[0] as u32, b[1] as u32, b[2] as u32, b[3] as u32);
+    if little {
+        b0 | b1 << 8 | b2 << 16 | b3 << 24
+    } else {
+        b3 | b2 << 8 | b1 << 16 | b0 << 24
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    little: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn align(&mut self, n: usize) {
+        self.pos = (self.pos + n - 1) & !(n - 1);
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            bail!("truncated d-bus message");
+        }
+        let b = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        let little = self.little;
+        Ok(read_u32(self.take(4)?, little))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+}
+
+// NOTE: we always write little endian messages
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Writer {
+        Writer { buf: Vec::new() }
+    }
+
+    fn align(&mut self, n: usize) {
+        while self.buf.len() % n != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        for i in 0..4 {
+            self.buf.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.align(8);
+        for i in 0..8 {
+            self.buf.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.u8(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn struct_start(&mut self) {
+        self.align(8);
+    }
+
+    // returns the position of the length, which is filled in by end_array
+    fn begin_array(&mut self, element_align: usize) -> (usize, usize) {
+        self.u32(0);
+        let len_pos = self.buf.len() - 4;
+        self.align(element_align);
+        (len_pos, self.buf.len())
+    }
+
+    fn end_array(&mut self, start: (usize, usize)) {
+        let (len_pos, data_pos) = start;
+        let len = (self.buf.len() - data_pos) as u32;
+        for i in 0..4 {
+            self.buf[len_pos + i] = (len >> (i * 8)) as u8;
+        }
+    }
+
+    fn variant_string(&mut self, s: &str) {
+        self.signature("s");
+        self.string(s);
+    }
+
+    fn property_u64(&mut self, key: &str, value: u64) {
+        self.struct_start();
+        self.string(key);
+        self.signature("t");
+        self.u64(value);
+    }
+
+    fn field(&mut self, code: u8, sig: &str, value: &str) {
+        self.struct_start();
+        self.u8(code);
+        self.signature(sig);
+        self.string(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{LinuxMemory, LinuxPids};
+    use std::fs::remove_file;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::sync::Mutex;
+    use std::thread;
+
+    const JOB: &'static str = "/org/freedesktop/systemd1/job/7";
+
+    lazy_static! {
+        // NOTE: the bus address comes from the environment, so only one
+        //       test can use a fake bus at a time
+        static ref ENV: Mutex<()> = Mutex::new(());
+    }
+
+    #[test]
+    fn parse_paths() {
+        let (slice, unit) = parse("machine.slice:railcar:abc").unwrap();
+        assert_eq!(slice, "machine.slice");
+        assert_eq!(unit, "railcar-abc.scope");
+        let (slice, unit) = parse("::abc").unwrap();
+        assert_eq!(slice, "system.slice");
+        assert_eq!(unit, "abc.scope");
+        for p in &["/foo", "a:b", "a:b:c:d", "a.slice:b:", "a.slice:b:../c"] {
+            assert!(parse(p).is_err(), "{} should be invalid", p);
+        }
+    }
+
+    #[test]
+    fn expand_slices() {
+        assert_eq!(expand_slice("-.slice").unwrap(), "");
+        assert_eq!(expand_slice("system.slice").unwrap(), "/system.slice");
+        assert_eq!(
+            expand_slice("a-b-c.slice").unwrap(),
+            "/a.slice/a-b.slice/a-b-c.slice"
+        );
+        for s in &["a", "a--b.slice", "-a.slice", "a-.slice", "a/b.slice"] {
+            assert!(expand_slice(s).is_err(), "{} should be invalid", s);
+        }
+        assert_eq!(
+            expand_path("user.slice:railcar:abc").unwrap(),
+            "/user.slice/railcar-abc.scope"
+        );
+    }
+
+    // builds a message from the bus, using a serial that never matches
+    // one of ours
+    fn message(
+        typ: u8,
+        reply_serial: u32,
+        name: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u8(b'l');
+        w.u8(typ);
+        w.u8(0);
+        w.u8(1);
+        w.u32(body.len() as u32);
+        w.u32(1000);
+        let fields = w.begin_array(8);
+        if reply_serial != 0 {
+            w.struct_start();
+            w.u8(FIELD_REPLY_SERIAL);
+            w.signature("u");
+            w.u32(reply_serial);
+        }
+        match typ {
+            ERROR => w.field(FIELD_ERROR_NAME, "s", name),
+            SIGNAL => w.field(FIELD_MEMBER, "s", name),
+            _ => {}
+        }
+        if !signature.is_empty() {
+            w.struct_start();
+            w.u8(FIELD_SIGNATURE);
+            w.signature("g");
+            w.signature(signature);
+        }
+        w.end_array(fields);
+        w.align(8);
+        w.buf.extend_from_slice(body);
+        w.buf
+    }
+
+    fn job_removed(job: &str, result: &str) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(7);
+        w.string(job);
+        w.string("railcar-abc.scope");
+        w.string(result);
+        message(SIGNAL, 0, "JobRemoved", "uoss", &w.buf)
+    }
+
+    fn job_reply() -> Vec<u8> {
+        let mut w = Writer::new();
+        w.string(JOB);
+        message(METHOD_RETURN, 3, "", "o", &w.buf)
+    }
+
+    // serves one connection like the system bus. After StartTransientUnit
+    // the replies are sent, and the body of the call is returned.
+    fn serve(name: &str, replies: Vec<Vec<u8>>) -> thread::JoinHandle<Vec<u8>> {
+        let path = format!(
+            "{}/railcar-{}-{}.sock",
+            env::temp_dir().display(),
+            name,
+            process::id()
+        );
+        let _ = remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        env::set_var("DBUS_SYSTEM_BUS_ADDRESS", format!("unix:path={}", path));
+        thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let _ = remove_file(&path);
+            let mut stream = BufReader::new(conn);
+            let mut nul = [0u8; 1];
+            stream.read_exact(&mut nul).unwrap();
+            assert_eq!(nul[0], 0);
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            let uid = getuid().to_string();
+            let hex: String =
+                uid.bytes().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(line, format!("AUTH EXTERNAL {}\r\n", hex));
+            stream.get_mut().write_all(b"OK 0123456789abcdef\r\n").unwrap();
+            line.clear();
+            stream.read_line(&mut line).unwrap();
+            assert_eq!(line, "BEGIN\r\n");
+            let mut bus = Bus {
+                stream: stream,
+                serial: 0,
+                signals: Vec::new(),
+            };
+            let mut serial = 0;
+            loop {
+                let msg = bus.read_message().unwrap();
+                assert_eq!(msg.typ, METHOD_CALL);
+                serial += 1;
+                let conn = bus.stream.get_mut();
+                match &*msg.member {
+                    "Hello" => {
+                        let mut w = Writer::new();
+                        w.string(":1.42");
+                        let reply =
+                            message(METHOD_RETURN, serial, "", "s", &w.buf);
+                        conn.write_all(&reply).unwrap();
+                    }
+                    "AddMatch" => {
+                        let rule = msg.reader().string().unwrap();
+                        assert!(rule.contains("member='JobRemoved'"));
+                        let reply = message(METHOD_RETURN, serial, "", "", &[]);
+                        conn.write_all(&reply).unwrap();
+                    }
+                    "StartTransientUnit" => {
+                        assert_eq!(serial, 3);
+                        for r in &replies {
+                            conn.write_all(r).unwrap();
+                        }
+                        return msg.body;
+                    }
+                    m => panic!("unexpected call {}", m),
+                }
+            }
+        })
+    }
+
+    fn resources() -> Option<LinuxResources> {
+        Some(LinuxResources {
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 20),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 10 }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn start_transient_unit() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        // NOTE: the signal for our job comes before the reply to make
+        //       sure that signals aren't lost while waiting for it
+        let server = serve(
+            "start",
+            vec![
+                job_removed("/org/freedesktop/systemd1/job/6", "failed"),
+                job_removed(JOB, "done"),
+                job_reply(),
+            ],
+        );
+        start_unit("machine.slice:railcar:abc", 1234, &resources()).unwrap();
+        let body = server.join().unwrap();
+
+        // the unit name and mode are the first two strings
+        let mut expected = vec![17, 0, 0, 0];
+        expected.extend_from_slice(b"railcar-abc.scope\0\0\0");
+        expected.extend_from_slice(&[7, 0, 0, 0]);
+        expected.extend_from_slice(b"replace\0");
+        assert_eq!(&body[..expected.len()], &expected[..]);
+
+        let mut r = Reader {
+            data: &body,
+            pos: expected.len(),
+            little: true,
+        };
+        let len = r.u32().unwrap() as usize;
+        r.align(8);
+        let end = r.pos + len;
+        let mut props = Vec::new();
+        while r.pos < end {
+            r.align(8);
+            let key = r.string().unwrap();
+            let sig = r.signature().unwrap();
+            let value = match &*sig {
+                "s" => r.string().unwrap(),
+                "b" => r.u32().unwrap().to_string(),
+                "au" => {
+                    assert_eq!(r.u32().unwrap(), 4);
+                    r.u32().unwrap().to_string()
+                }
+                "t" => {
+                    r.align(8);
+                    let lo = r.u32().unwrap() as u64;
+                    let hi = r.u32().unwrap() as u64;
+                    (hi << 32 | lo).to_string()
+                }
+                s => panic!("unexpected signature {} for {}", s, key),
+            };
+            props.push((key, value));
+        }
+        let memory = if cgroups::is_unified() {
+            "MemoryMax"
+        } else {
+            "MemoryLimit"
+        };
+        let expected = vec![
+            ("Description", "railcar container railcar-abc.scope"),
+            ("Slice", "machine.slice"),
+            ("Delegate", "1"),
+            ("DefaultDependencies", "0"),
+            ("PIDs", "1234"),
+            (memory, "1048576"),
+            ("TasksMax", "10"),
+        ];
+        let props: Vec<(&str, &str)> =
+            props.iter().map(|p| (&p.0[..], &p.1[..])).collect();
+        assert_eq!(props, expected);
+        // the aux array is empty, but still padded for its elements
+        assert_eq!(r.u32().unwrap(), 0);
+        r.align(8);
+        assert_eq!(r.pos, body.len());
+    }
+
+    #[test]
+    fn error_reply() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let mut w = Writer::new();
+        w.string("Unit railcar-abc.scope already exists.");
+        let error = message(
+            ERROR,
+            3,
+            "org.freedesktop.systemd1.UnitExists",
+            "s",
+            &w.buf,
+        );
+        let server = serve("error", vec![error]);
+        let err = start_unit("::railcar-abc", 1234, &None).unwrap_err();
+        server.join().unwrap();
+        let msg: Vec<String> = err.iter().map(|e| e.to_string()).collect();
+        let msg = msg.join(": ");
+        assert!(msg.contains("UnitExists"), "{}", msg);
+        assert!(msg.contains("already exists"), "{}", msg);
+    }
+
+    #[test]
+    fn failed_job() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let server =
+            serve("failed", vec![job_reply(), job_removed(JOB, "failed")]);
+        let err = start_unit("::abc", 1234, &None).unwrap_err();
+        server.join().unwrap();
+        assert!(err.to_string().contains("failed"), "{}", err);
+    }
+}
@@ -0,0 +1,156 @@
+use errors::*;
+use libc;
+use nix::Errno;
+use nix::fcntl::{open, O_CLOEXEC, O_NOCTTY, O_RDWR};
+use nix::mount::{mount, MS_BIND};
+use nix::poll::{poll, PollFd, POLLIN, POLLHUP, POLLERR, POLLNVAL, EventFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, dup2, read, setsid, write};
+use nix_ext::{unlockpt, ptsname};
+use std::mem::zeroed;
+use std::os::unix::io::RawFd;
+
+const CONSOLE: &'static str = "/dev/console";
+
+// opens a new pty using /dev/ptmx from the current mount namespace and
+// returns the master, slave and the path of the slave
+pub fn open_pty() -> Result<(RawFd, RawFd, String)> {
+    let master = open("/dev/ptmx", O_RDWR | O_NOCTTY | O_CLOEXEC, Mode::empty())
+        .chain_err(|| "failed to open /dev/ptmx")?;
+    unlockpt(master).chain_err(|| "failed to unlock pty")?;
+    let name = ptsname(master).chain_err(|| "failed to get pty name")?;
+    let slave = open(&*name, O_RDWR | O_NOCTTY | O_CLOEXEC, Mode::empty())
+        .chain_err(|| format!("failed to open {}", name))?;
+    Ok((master, slave, name))
+}
+
+pub fn set_size(fd: RawFd, height: u64, width: u64) -> Result<()> {
+    let ws = libc::winsize {
+        ws_row: height as u16,
+        ws_col: width as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let res = unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &ws) };
+    Errno::result(res).chain_err(|| "failed to set console size")?;
+    Ok(())
+}
+
+// bind mounts the pty slave over /dev/console, which must already exist
+pub fn mount_console(slave: &str) -> Result<()> {
+    debug!("bind mounting {} to {}", slave, CONSOLE);
+    mount(Some(slave), CONSOLE, None::<&str>, MS_BIND, None::<&str>)
+        .chain_err(|| format!("failed to mount {}", CONSOLE))?;
+    Ok(())
+}
+
+// makes fd the controlling terminal and stdio of the current process
+pub fn set_controlling(fd: RawFd) -> Result<()> {
+    setsid()?;
+    if unsafe { libc::ioctl(fd, libc::TIOCSCTTY) } < 0 {
+        warn!("could not TIOCSCTTY");
+    };
+    set_stdio(fd)
+}
+
+// makes fd the stdio of the current process
+pub fn set_stdio(fd: RawFd) -> Result<()> {
+    dup2(fd, 0).chain_err(|| "could not dup tty to stdin")?;
+    dup2(fd, 1).chain_err(|| "could not dup tty to stdout")?;
+    dup2(fd, 2).chain_err(|| "could not dup tty to stderr")?;
+    Ok(())
+}
+
+fn write_all(fd: RawFd, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        match write(fd, data) {
+            Ok(n) => data = &data[n..],
+            Err(e) => {
+                if e.errno() != Errno::EINTR {
+                    return Err(e).chain_err(|| "failed to write to tty");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// puts the terminal into raw mode and returns the previous settings
+fn make_raw(fd: RawFd) -> Option<libc::termios> {
+    unsafe {
+        if libc::isatty(fd) != 1 {
+            return None;
+        }
+        let mut t: libc::termios = zeroed();
+        if libc::tcgetattr(fd, &mut t) != 0 {
+            return None;
+        }
+        let orig = t;
+        libc::cfmakeraw(&mut t);
+        if libc::tcsetattr(fd, libc::TCSANOW, &t) != 0 {
+            return None;
+        }
+        Some(orig)
+    }
+}
+
+// copies the window size of the terminal on fd to the pty master
+fn copy_size(fd: RawFd, master: RawFd) {
+    unsafe {
+        let mut ws: libc::winsize = zeroed();
+        if libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) == 0 {
+            libc::ioctl(master, libc::TIOCSWINSZ, &ws);
+        }
+    }
+}
+
+// copies data between our stdio and the pty master until the container
+// side of the pty is closed
+pub fn proxy(master: RawFd, copy_window: bool) -> Result<()> {
+    if copy_window {
+        copy_size(0, master);
+    }
+    let orig = make_raw(0);
+    defer!(if let Some(ref t) = orig {
+        unsafe { libc::tcsetattr(0, libc::TCSANOW, t) };
+    });
+    let mut stdin_open = true;
+    let buf: &mut [u8] = &mut [0; 4096];
+    loop {
+        let mut pfds = vec![PollFd::new(master, POLLIN, EventFlags::empty())];
+        if stdin_open {
+            pfds.push(PollFd::new(0, POLLIN, EventFlags::empty()));
+        }
+        if let Err(e) = poll(&mut pfds, -1) {
+            if e.errno() != Errno::EINTR {
+                return Err(e).chain_err(|| "unable to poll tty");
+            }
+            continue;
+        }
+        if let Some(events) = pfds[0].revents() {
+            if events.intersects(POLLIN) {
+                match read(master, buf) {
+                    Err(ref e) if e.errno() == Errno::EINTR => {}
+                    // EIO means the slave was closed
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => write_all(1, &buf[..n])?,
+                }
+            } else if events.intersects(POLLHUP | POLLERR | POLLNVAL) {
+                break;
+            }
+        }
+        if stdin_open {
+            if let Some(events) = pfds[1].revents() {
+                if events.intersects(POLLIN | POLLHUP) {
+                    match read(0, buf) {
+                        Err(ref e) if e.errno() == Errno::EINTR => {}
+                        Ok(0) | Err(_) => stdin_open = false,
+                        Ok(n) => write_all(master, &buf[..n])?,
+                    }
+                }
+            }
+        }
+    }
+    close(master)?;
+    Ok(())
+}